@@ -0,0 +1,25 @@
+// Sky colour and distance fog, shared by the sky pass and the terrain so distant
+// terrain fades into the sky drawn behind it.
+#include "globals.wgsl"
+
+// Colour of the sky in a direction, the horizon colour continues below the horizon
+fn sky_color(dir: vec3<f32>) -> vec3<f32> {
+    let height = clamp(dir.y, 0.0, 1.0);
+    return mix(camera.sky_horizon.rgb, camera.sky_zenith.rgb, sqrt(height));
+}
+
+// Brightness of full sky light at night
+const NIGHT_LIGHT: f32 = 0.15;
+
+// Brightness of full sky light at the current time of day, scaling the sky light
+// baked in the terrain
+fn sky_brightness() -> f32 {
+    return mix(NIGHT_LIGHT, 1.0, camera.sun.w);
+}
+
+// Fog grows with the horizontal distance to the camera, the way chunks are loaded
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - camera.eye_position.xyz;
+    let fog = smoothstep(camera.fog.x, camera.fog.y, length(offset.xz));
+    return mix(color, sky_color(normalize(offset)), fog);
+}
@@ -0,0 +1,39 @@
+// Terrain vertices, packed as described by BlockVertex. Set CHUNK_ORIGINS_GROUP to the
+// bind group the chunk origins are bound to, CHUNK_SLOTS is the amount of chunks the
+// terrain keeps loaded.
+
+struct VertexInput {
+    @location(0) position: u32,
+    @location(1) texture: u32,
+}
+
+// World position of the chunk in every slot
+@group(CHUNK_ORIGINS_GROUP) @binding(0)
+var<uniform> chunk_origins: array<vec4<f32>, CHUNK_SLOTS>;
+
+fn block_world_position(vertex: VertexInput) -> vec3<f32> {
+    let local_position = vec3<u32>(vertex.position & 31u, (vertex.position >> 5u) & 127u, (vertex.position >> 12u) & 31u);
+    return chunk_origins[vertex.texture >> 22u].xyz + vec3<f32>(local_position);
+}
+
+fn block_tex_coords(vertex: VertexInput) -> vec2<f32> {
+    return vec2<f32>(f32(vertex.texture & 31u), f32((vertex.texture >> 5u) & 31u));
+}
+
+fn block_layer(vertex: VertexInput) -> u32 {
+    return (vertex.texture >> 10u) & 4095u;
+}
+
+fn block_side(vertex: VertexInput) -> u32 {
+    return (vertex.position >> 17u) & 7u;
+}
+
+// Normal of a face from its side, in the order of QuadSide: +y, -y, +x, -x, +z, -z
+fn side_normal(side: u32) -> vec3<f32> {
+    let sign = select(1.0, -1.0, (side & 1u) == 1u);
+    switch side / 2u {
+        case 0u: { return vec3<f32>(0.0, sign, 0.0); }
+        case 1u: { return vec3<f32>(sign, 0.0, 0.0); }
+        default: { return vec3<f32>(0.0, 0.0, sign); }
+    }
+}
@@ -0,0 +1,23 @@
+// Per frame uniforms, `Globals` on the Rust side. Set GLOBALS_GROUP to the bind group
+// the pipeline binds them to.
+#ifndef GLOBALS_GROUP
+#define GLOBALS_GROUP 0
+#endif
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // xyz: camera position in world space
+    eye_position: vec4<f32>,
+    debug_flags: vec4<u32>,
+    // x: game time in seconds, y: time of day from 0 at sunrise to 1 at the next one
+    time: vec4<f32>,
+    // xyz: direction towards the sun, w: daylight from 0 at night to 1 during the day
+    sun: vec4<f32>,
+    // x: distance fog starts at, y: distance everything is hidden in the sky
+    fog: vec4<f32>,
+    sky_horizon: vec4<f32>,
+    sky_zenith: vec4<f32>,
+};
+@group(GLOBALS_GROUP) @binding(0)
+var<uniform> camera: CameraUniform;
@@ -0,0 +1,92 @@
+// Post processing, `PostProcess` on the Rust side. Every stage draws a triangle
+// covering the screen and reads the frame of the previous one: fs_tonemap brings the
+// HDR frame to the display range, fs_fxaa smooths its edges.
+
+struct PostProcessUniform {
+    // x: tonemapping enabled
+    flags: vec4<u32>,
+    // x: brightness, the exposure the frame is scaled by, y: gamma
+    params: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> post: PostProcessUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32(index / 2u) * 4.0 - 1.0, f32(index % 2u) * 4.0 - 1.0);
+    var out: VertexOutput;
+    out.uv = ndc * vec2<f32>(0.5, -0.5) + 0.5;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    return out;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSampleLevel(t_source, s_source, in.uv, 0.0).rgb * post.params.x;
+    if post.flags.x != 0u {
+        color = aces(color);
+    }
+    color = pow(saturate(color), vec3<f32>(1.0 / post.params.y));
+    return vec4<f32>(color, 1.0);
+}
+
+// How far FXAA blurs along an edge, in pixels
+const FXAA_SPAN_MAX: f32 = 8.0;
+const FXAA_REDUCE_MUL: f32 = 0.125;
+const FXAA_REDUCE_MIN: f32 = 0.0078125;
+
+fn fxaa_sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// Perceived brightness, edges are found where it changes. The frame is in linear RGB,
+// the square root is close to the display encoding.
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(sqrt(color), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// Blurs along the edge running through the pixel, found from the luma of its corners
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let luma_nw = luma(fxaa_sample(in.uv + vec2<f32>(-0.5, -0.5) * texel));
+    let luma_ne = luma(fxaa_sample(in.uv + vec2<f32>(0.5, -0.5) * texel));
+    let luma_sw = luma(fxaa_sample(in.uv + vec2<f32>(-0.5, 0.5) * texel));
+    let luma_se = luma(fxaa_sample(in.uv + vec2<f32>(0.5, 0.5) * texel));
+    let color_m = fxaa_sample(in.uv);
+    let luma_m = luma(color_m);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let dir_scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * dir_scale, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let near = 0.5 * (fxaa_sample(in.uv + dir * (1.0 / 3.0 - 0.5)) + fxaa_sample(in.uv + dir * (2.0 / 3.0 - 0.5)));
+    let far = near * 0.5 + 0.25 * (fxaa_sample(in.uv - dir * 0.5) + fxaa_sample(in.uv + dir * 0.5));
+    // the wider blur crossed another edge
+    let luma_far = luma(far);
+    if luma_far < luma_min || luma_far > luma_max {
+        return vec4<f32>(near, 1.0);
+    }
+    return vec4<f32>(far, 1.0);
+}
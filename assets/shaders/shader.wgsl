@@ -1,38 +1,119 @@
-// Vertex shader
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0) // 1.
-var<uniform> camera: CameraUniform;
+// Terrain. Wireframe pipelines are compiled with WIREFRAME defined, CHUNK_SLOTS is the
+// amount of chunks the terrain keeps loaded. TEXTURE_ATLAS samples block textures from
+// the atlas instead of the texture array.
+#define GLOBALS_GROUP 1
+#ifndef TEXTURE_ATLAS
+#define TEXTURE_ARRAY
+#endif
+#define CHUNK_ORIGINS_GROUP 2
+#include "globals.wgsl"
+#include "atmosphere.wgsl"
+#include "shadows.wgsl"
+#include "texture.wgsl"
+#include "block_vertex.wgsl"
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-}
+// Vertex shader
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) block_light: f32,
+    @location(4) sky_light: f32,
+    @location(5) ao: f32,
+    @location(6) @interpolate(flat) side: u32,
+}
+
+struct LayerAnimation {
+    frame_count: u32,
+    frame_time: f32,
+    padding: vec2<u32>,
+}
+@group(0) @binding(2)
+var<uniform> animations: array<LayerAnimation, 256>;
+
+// Animated textures are stored as consecutive layers, one per frame
+fn animated_layer(layer: u32) -> u32 {
+    let animation = animations[layer];
+    if animation.frame_count <= 1u {
+        return layer;
+    }
+    let frame = u32(camera.time.x / animation.frame_time) % animation.frame_count;
+    return layer + frame;
+}
+
+// Brightness of a vertex in a fully enclosed corner
+const AO_DARKEST: f32 = 0.45;
+
+// Every level below 15 is 20% darker
+fn light_brightness(level: u32) -> f32 {
+    return pow(0.8, f32(15u - min(level, 15u)));
 }
 
 @vertex
 fn vs_main(
     vertex: VertexInput,
 ) -> VertexOutput {
+    let light = (vertex.position >> 22u) & 255u;
+    let ao = (vertex.position >> 20u) & 3u;
+    let world_position = block_world_position(vertex);
+
     var out: VertexOutput;
-    out.tex_coords = vertex.tex_coords;
-    out.clip_position = camera.view_proj * vec4<f32>(vertex.position, 1.0);
+    out.tex_coords = block_tex_coords(vertex);
+    out.layer = animated_layer(block_layer(vertex));
+    out.block_light = light_brightness(light & 15u);
+    out.sky_light = light_brightness(light >> 4u);
+    out.ao = mix(AO_DARKEST, 1.0, f32(ao) / 3.0);
+    out.side = block_side(vertex);
+    out.world_position = world_position;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
     return out;
 }
+
 // Fragment shader
 
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0)@binding(1)
-var s_diffuse: sampler;
+// Tint of the light emitted by blocks
+const BLOCK_LIGHT_COLOR: vec3<f32> = vec3<f32>(1.0, 0.85, 0.65);
+
+// Sky light left in the shadow of the sun during the day
+const SHADOW_LIGHT: f32 = 0.55;
+
+// Debug view tinting every face with a colour per direction: x red, y green, z blue,
+// darker on the negative side.
+fn face_color(in: VertexOutput, color: vec4<f32>) -> vec4<f32> {
+    let normal = side_normal(in.side);
+    let side = select(1.0, 0.4, normal.x + normal.y + normal.z < 0.0);
+    let tint = vec4<f32>(abs(normal) * side, color.a);
+    return select(color, mix(color, tint, 0.75), camera.debug_flags.x != 0u);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let diffuse = face_color(in, sample_diffuse(in.tex_coords, in.layer));
+    let shadow = mix(1.0, mix(SHADOW_LIGHT, 1.0, sun_visibility(in.world_position, side_normal(in.side))), camera.sun.w);
+    let light = max(vec3<f32>(in.sky_light * sky_brightness() * shadow), BLOCK_LIGHT_COLOR * in.block_light);
+    let color = vec4<f32>(apply_fog(diffuse.rgb * light * in.ao, in.world_position), diffuse.a);
+#ifdef WIREFRAME
+    // edges of see-through blocks stay visible
+    return vec4<f32>(color.rgb, 1.0);
+#else
+    return color;
+#endif
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return shade(in);
+}
+
+// Cutout blocks keep depth writes, so their transparent texels are discarded
+@fragment
+fn fs_cutout(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+#ifndef WIREFRAME
+    if color.a < 0.5 {
+        discard;
+    }
+#endif
+    return color;
 }
- 
\ No newline at end of file
@@ -0,0 +1,38 @@
+// Depth of the terrain from the sun, into one cascade of the shadow map. CHUNK_SLOTS is
+// the amount of chunks the terrain keeps loaded, TEXTURE_ATLAS samples the block atlas.
+#define CHUNK_ORIGINS_GROUP 2
+#ifndef TEXTURE_ATLAS
+#define TEXTURE_ARRAY
+#endif
+#include "texture.wgsl"
+#include "block_vertex.wgsl"
+
+// Light view projection of the cascade
+@group(1) @binding(0)
+var<uniform> cascade: mat4x4<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) layer: u32,
+}
+
+@vertex
+fn vs_main(
+    vertex: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = block_tex_coords(vertex);
+    out.layer = block_layer(vertex);
+    out.clip_position = cascade * vec4<f32>(block_world_position(vertex), 1.0);
+    return out;
+}
+
+// Transparent texels of cutout blocks let light through. Animated textures cast the
+// shadow of their first frame.
+@fragment
+fn fs_cutout(in: VertexOutput) {
+    if sample_diffuse(in.tex_coords, in.layer).a < 0.5 {
+        discard;
+    }
+}
@@ -0,0 +1,53 @@
+// Sun shadows from the cascaded shadow map, `ShadowMap` on the Rust side. Include after
+// globals.wgsl, the cascades are bound next to the globals.
+
+// Same as SHADOW_CASCADES
+const SHADOW_CASCADES: u32 = 3u;
+
+struct ShadowUniform {
+    // world space, relative to the camera's chunk, to the shadow map of every cascade
+    cascades: array<mat4x4<f32>, 3>,
+    // distance from the camera every cascade reaches
+    splits: vec4<f32>,
+};
+@group(GLOBALS_GROUP) @binding(1)
+var<uniform> shadow: ShadowUniform;
+@group(GLOBALS_GROUP) @binding(2)
+var shadow_map: texture_depth_2d_array;
+@group(GLOBALS_GROUP) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+// Share of sunlight reaching `world_position` on a face with `normal`, 1 past the last
+// cascade. Faces turned away from the sun are fully shadowed.
+fn sun_visibility(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if dot(normal, camera.sun.xyz) <= 0.0 {
+        return 0.0;
+    }
+
+    let distance = length(world_position - camera.eye_position.xyz);
+    var cascade = 0u;
+    while cascade < SHADOW_CASCADES && distance > shadow.splits[cascade] {
+        cascade += 1u;
+    }
+    if cascade == SHADOW_CASCADES {
+        return 1.0;
+    }
+
+    // the first row of an orthographic projection scales world units by 2 / width
+    let matrix = shadow.cascades[cascade];
+    let size = f32(textureDimensions(shadow_map).x);
+    let texel = 2.0 / (length(vec3<f32>(matrix[0].x, matrix[1].x, matrix[2].x)) * size);
+    // moving along the normal keeps faces from shadowing themselves
+    let position = matrix * vec4<f32>(world_position + normal * texel * 1.5, 1.0);
+    let uv = position.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    // 3x3 percentage closer filtering
+    var visibility = 0.0;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) / size;
+            visibility += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, position.z);
+        }
+    }
+    return visibility / 9.0;
+}
@@ -0,0 +1,39 @@
+// Sky gradient with the sun and the moon, a triangle covering the screen drawn before
+// the terrain.
+#include "atmosphere.wgsl"
+
+// Cosines of the angular radius of the sun and the moon
+const SUN_SIZE: f32 = 0.9988;
+const MOON_SIZE: f32 = 0.9992;
+const SUN_COLOR: vec3<f32> = vec3<f32>(1.0, 0.92, 0.75);
+const MOON_COLOR: vec3<f32> = vec3<f32>(0.75, 0.8, 0.9);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32(index / 2u) * 4.0 - 1.0, f32(index % 2u) * 4.0 - 1.0);
+    var out: VertexOutput;
+    out.ndc = ndc;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // any depth inside the frustum lies on the view ray of the pixel
+    let world = camera.inv_view_proj * vec4<f32>(in.ndc, 0.5, 1.0);
+    let dir = normalize(world.xyz / world.w - camera.eye_position.xyz);
+    let sun = dot(dir, camera.sun.xyz);
+    // both sink behind the horizon instead of showing through the sky below it
+    let above_horizon = smoothstep(-0.02, 0.02, dir.y);
+
+    var color = sky_color(dir);
+    color += SUN_COLOR * pow(max(sun, 0.0), 256.0) * 0.5 * above_horizon;
+    color = mix(color, SUN_COLOR, smoothstep(SUN_SIZE, SUN_SIZE + 0.0002, sun) * above_horizon);
+    color = mix(color, MOON_COLOR, smoothstep(MOON_SIZE, MOON_SIZE + 0.0002, -sun) * above_horizon);
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,33 @@
+// Diffuse texture of textured geometry at group 0. With TEXTURE_ARRAY defined it is a
+// texture array and `layer` picks the layer. With TEXTURE_ATLAS it is an atlas and
+// `layer` picks the region `tex_coords` repeat in. Otherwise `layer` is ignored.
+@group(0) @binding(0)
+#ifdef TEXTURE_ARRAY
+var t_diffuse: texture_2d_array<f32>;
+#else
+var t_diffuse: texture_2d<f32>;
+#endif
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+#ifdef TEXTURE_ATLAS
+// UV offset (xy) and size (zw) of every layer in the atlas
+@group(0) @binding(3)
+var<uniform> atlas_regions: array<vec4<f32>, 256>;
+#endif
+
+fn sample_diffuse(tex_coords: vec2<f32>, layer: u32) -> vec4<f32> {
+#ifdef TEXTURE_ARRAY
+    return textureSample(t_diffuse, s_diffuse, tex_coords, layer);
+#else
+#ifdef TEXTURE_ATLAS
+    // the gradients of the unwrapped coordinates keep the mip level continuous across
+    // the seams where fract wraps
+    let region = atlas_regions[layer];
+    let uv = region.xy + fract(tex_coords) * region.zw;
+    return textureSampleGrad(t_diffuse, s_diffuse, uv, dpdx(tex_coords) * region.zw, dpdy(tex_coords) * region.zw);
+#else
+    return textureSample(t_diffuse, s_diffuse, tex_coords);
+#endif
+#endif
+}
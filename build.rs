@@ -1,22 +1,33 @@
 use anyhow::*;
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
+use glob::glob;
 use std::env;
+use std::fs;
+use std::path::Path;
 
 fn main() -> Result<()> {
     // This tells Cargo to rerun this script if something in /res/ changes.
     println!("cargo:rerun-if-changed=assets/*");
+    println!("cargo:rerun-if-changed=assets/textures/block");
 
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
     let mut paths_to_copy = Vec::new();
     paths_to_copy.push("assets/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
+
+    // Embed every block texture, a fallback for when the game can't find the block
+    // texture directory at startup.
+    let mut block_textures = String::from("&[\n");
+    for entry in glob("assets/textures/block/*.png")? {
+        let path = fs::canonicalize(entry?)?;
+        let name = path.file_stem().and_then(|s| s.to_str()).context("invalid texture name")?;
+        block_textures += &format!("    ({:?}, include_bytes!({:?}) as &[u8]),\n", name, path);
+    }
+    block_textures += "]\n";
+    fs::write(Path::new(&out_dir).join("block_textures.rs"), block_textures)?;
 
     Ok(())
 }
-
- 
-
- 
\ No newline at end of file
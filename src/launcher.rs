@@ -8,12 +8,89 @@ use winit::{
         window::WindowBuilder,
     };
 
-use crate::Game;
+use clap::{builder::{PossibleValuesParser, TypedValueParser}, Arg, ArgAction, Command};
+
+use crate::{resource_pack::ResourcePacks, settings::Settings, Game};
 
 pub fn run() {
 
     env_logger::init();
 
+    let args = Command::new("wgpucraft")
+        .arg(Arg::new("resource-pack")
+            .long("resource-pack")
+            .value_name("DIR")
+            .action(ArgAction::Append)
+            .help("Resource pack directory, can be repeated, the first one has the highest priority"))
+        .arg(Arg::new("anisotropy")
+            .long("anisotropy")
+            .value_name("SAMPLES")
+            .value_parser(clap::value_parser!(u16).range(1..=16))
+            .help("Anisotropic filtering of block textures, 1 disables it"))
+        .arg(Arg::new("texture-atlas")
+            .long("texture-atlas")
+            .action(ArgAction::SetTrue)
+            .help("Pack block textures into an atlas instead of a texture array"))
+        .arg(Arg::new("screenshot-scale")
+            .long("screenshot-scale")
+            .value_name("SCALE")
+            .value_parser(clap::value_parser!(u32).range(1..=16))
+            .help("Multiple of the window size Shift+F2 screenshots are taken at"))
+        .arg(Arg::new("msaa")
+            .long("msaa")
+            .value_name("SAMPLES")
+            .value_parser(PossibleValuesParser::new(["1", "2", "4", "8"]).map(|samples| samples.parse::<u32>().unwrap()))
+            .help("Multisample antialiasing samples per pixel, 1 disables it, cycled with F3"))
+        .arg(Arg::new("present-mode")
+            .long("present-mode")
+            .value_name("MODE")
+            .value_parser(["vsync", "mailbox", "immediate"])
+            .help("How frames are presented, defaults to vsync, cycled with F4"))
+        .arg(Arg::new("no-tonemapping")
+            .long("no-tonemapping")
+            .action(ArgAction::SetTrue)
+            .help("Clip bright colours instead of tonemapping them, toggled with F10"))
+        .arg(Arg::new("no-fxaa")
+            .long("no-fxaa")
+            .action(ArgAction::SetTrue)
+            .help("Disable FXAA antialiasing, toggled with F11"))
+        .arg(Arg::new("brightness")
+            .long("brightness")
+            .value_name("EXPOSURE")
+            .value_parser(positive_f32)
+            .help("Exposure the frame is scaled by before tonemapping, defaults to 1"))
+        .arg(Arg::new("gamma")
+            .long("gamma")
+            .value_name("GAMMA")
+            .value_parser(positive_f32)
+            .help("Gamma correction applied after tonemapping, above 1 brightens dark colours"))
+        .arg(Arg::new("watch-shaders")
+            .long("watch-shaders")
+            .value_name("DIR")
+            .num_args(0..=1)
+            .default_missing_value(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shaders"))
+            .help("Load shaders from DIR and reload them when they change, defaults to the source assets"))
+        .arg(Arg::new("block-textures")
+            .long("block-textures")
+            .value_name("DIR")
+            .help("Directory of the block textures packed at startup, defaults to assets/textures/block"))
+        .arg(Arg::new("world")
+            .long("world")
+            .value_name("DIR")
+            .help("Directory the world is saved to, defaults to saves/world"))
+        .arg(Arg::new("time-scale")
+            .long("time-scale")
+            .value_name("SCALE")
+            .value_parser(clap::value_parser!(f32))
+            .help("Speed of the day/night cycle, 1 is a 20 minute day and 0 stops it"))
+        .get_matches();
+    let settings = Settings::from_args(&args);
+    let resource_packs = ResourcePacks::new(
+        args.get_many::<String>("resource-pack").unwrap_or_default().map(Into::into).collect()
+    )
+        .with_shader_dir(settings.shader_dir.clone())
+        .with_block_texture_dir(settings.block_texture_dir.clone());
+
     //TODO: establish this parameters from settings
     let runtime = tokio::runtime::Builder::new_current_thread()
         .worker_threads(4)
@@ -26,7 +103,7 @@ pub fn run() {
     event_loop.set_control_flow(ControlFlow::Poll);
 
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut game = Game::new(window, runtime);
+    let mut game = Game::new(window, runtime, resource_packs, settings);
     game.initialize();
     
     event_loop.run(move | event, elwt: &winit::event_loop::EventLoopWindowTarget<()> | {
@@ -49,4 +126,14 @@ pub fn run() {
             _ => ()
         }
     }).unwrap();
-}
\ No newline at end of file
+}
+
+
+// Value parser of settings that only make sense above 0.
+fn positive_f32(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -1,15 +1,22 @@
 
 pub mod launcher;
 pub mod render;
+pub mod resource_pack;
+pub mod save;
 pub mod scene;
+pub mod screenshot;
+pub mod settings;
 
-use render::renderer::Renderer;
+use render::{hot_reload::{join_errors, ShaderWatcher}, renderer::Renderer};
+use resource_pack::ResourcePacks;
+use save::WorldSave;
 use scene::Scene;
+use settings::{PostProcessSettings, PresentMode, Settings};
 use tokio::runtime::Runtime;
 use winit::{
         event_loop::EventLoopWindowTarget,
         event::{WindowEvent, DeviceEvent, KeyEvent, ElementState},
-        keyboard::{PhysicalKey, KeyCode},
+        keyboard::{PhysicalKey, KeyCode, ModifiersState},
         window::Window
     };
 
@@ -27,32 +34,128 @@ pub enum GameState {
 pub struct Game {
     pub window: Window,
     renderer: Renderer,
+    // kept alive for the renderer, which blocks on it through a handle
+    _runtime: Runtime,
     scene: Scene,
-    state: GameState
+    state: GameState,
+    resource_packs: ResourcePacks,
+    packs_enabled: bool,
+    settings: Settings,
+    shader_watcher: Option<ShaderWatcher>,
+    modifiers: ModifiersState,
 
 }
 
 impl Game {
 
-    pub fn new(window: Window, runtime: Runtime) -> Self {
+    pub fn new(window: Window, runtime: Runtime, resource_packs: ResourcePacks, mut settings: Settings) -> Self {
 
         let mut renderer = Renderer::new(&window, &runtime);
+        settings.graphics.msaa = renderer.set_sample_count(settings.graphics.msaa);
+        settings.graphics.present_mode = renderer.set_present_mode(settings.graphics.present_mode);
+        renderer.set_post_process(&settings.graphics.post_process);
+        if let Err(e) = renderer.reload_shaders(&resource_packs) {
+            eprintln!("Failed to load the post processing shaders of the resource packs: {:?}", e);
+        }
 
-        let scene = Scene::new(&mut renderer);
+        let mut scene = Scene::new(&mut renderer, &resource_packs, &settings);
+        match WorldSave::load(&settings.world_dir) {
+            Ok(Some(save)) => scene.load(&save),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to load the world, starting a new one: {:?}", e),
+        }
+        let shader_watcher = settings.shader_dir.as_deref().map(|dir| {
+            println!("watching shaders in {}", dir.display());
+            ShaderWatcher::new(dir)
+        });
 
         Self {
             window,
             renderer,
+            _runtime: runtime,
             scene,
             state: GameState::PLAYING,
+            resource_packs,
+            packs_enabled: true,
+            settings,
+            shader_watcher,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    fn active_resource_packs(&self) -> ResourcePacks {
+        if self.packs_enabled {
+            self.resource_packs.clone()
+        } else {
+            ResourcePacks::embedded()
+                .with_shader_dir(self.settings.shader_dir.clone())
+                .with_block_texture_dir(self.settings.block_texture_dir.clone())
         }
     }
 
+    /// Toggle between the configured resource packs and the embedded assets, the packs
+    /// are read from disk again every time they are enabled.
+    pub fn toggle_resource_packs(&mut self) {
+        self.packs_enabled = !self.packs_enabled;
+        let packs = self.active_resource_packs();
+        println!("switching to resource packs {:?}", packs.packs());
+
+        let loaded = join_errors([
+            self.scene.set_resource_packs(&self.renderer, &packs, &self.settings),
+            self.renderer.reload_shaders(&packs),
+        ]);
+        if let Err(e) = loaded {
+            eprintln!("Failed to load resource packs: {:?}", e);
+        }
+    }
+
+    /// Switch to the next MSAA sample count the adapter supports, wrapping around to 1.
+    /// The scene's pipelines are recreated for it, if that fails the previous sample
+    /// count is restored.
+    fn cycle_msaa(&mut self) {
+        let previous = self.renderer.sample_count();
+        let counts = self.renderer.supported_sample_counts();
+        let next = counts.iter().copied().find(|&count| count > previous).unwrap_or(counts[0]);
+
+        self.settings.graphics.msaa = self.renderer.set_sample_count(next);
+        let packs = self.active_resource_packs();
+        match self.scene.reload_shaders(&self.renderer, &packs) {
+            Ok(()) => println!("msaa: {}x", self.settings.graphics.msaa),
+            Err(e) => {
+                eprintln!("Failed to recreate the pipelines for {}x msaa: {:?}", next, e);
+                self.settings.graphics.msaa = self.renderer.set_sample_count(previous);
+                // pipelines that were recreated go back to the previous count
+                let _ = self.scene.reload_shaders(&self.renderer, &packs);
+            }
+        }
+    }
+
+    /// Switch to the next present mode, the renderer falls back to the closest one the
+    /// surface supports.
+    fn cycle_present_mode(&mut self) {
+        let modes = PresentMode::ALL;
+        let current = modes.iter().position(|&mode| mode == self.settings.graphics.present_mode).unwrap_or(0);
+        let requested = modes[(current + 1) % modes.len()];
+        self.settings.graphics.present_mode = self.renderer.set_present_mode(requested);
+        println!("present mode: {:?} (requested {:?})", self.settings.graphics.present_mode, requested);
+    }
+
+    /// Turn a post processing stage on or off.
+    fn toggle_post_process(&mut self, toggle: impl FnOnce(&mut PostProcessSettings)) {
+        let post_process = &mut self.settings.graphics.post_process;
+        toggle(post_process);
+        println!("post processing: {:?}", post_process);
+        self.renderer.set_post_process(post_process);
+    }
+
     //TODO: add global settings as parameter
     pub fn handle_window_event(&mut self, event: WindowEvent, elwt: &EventLoopWindowTarget<()>) {
         if !self.scene.handle_input_event(&event, &self.state) {
         match event {
             WindowEvent::CloseRequested  => {
+                if let Err(e) = self.scene.save().save(&self.settings.world_dir) {
+                    eprintln!("Failed to save the world: {:?}", e);
+                }
                 elwt.exit()
             },
 
@@ -64,7 +167,8 @@ impl Game {
                 let dt = now - self.renderer.last_render_time;
                 self.renderer.last_render_time = now;
                 self.update(dt);
-                match self.renderer.render(&self.scene.terrain, &self.scene.globals_bind_group) {
+                let result = self.renderer.render(&self.scene.render_graph());
+                match result {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
                     Err(wgpu::SurfaceError::Lost) => self.resize(self.renderer.size),
@@ -73,8 +177,11 @@ impl Game {
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
                     Err(e) => eprintln!("{:?}", e)
                 }
-                
+                if let Some(screenshot) = self.renderer.take_screenshot() {
+                    save_screenshot(screenshot);
+                }
             },
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
             // WindowEvent::MouseWheel { delta, .. } => {
             //     self.scene.camera.camera_controller.process_scroll(&delta);
             // },
@@ -108,6 +215,64 @@ impl Game {
                     
                 }
             }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key:PhysicalKey::Code(KeyCode::F9),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => self.toggle_resource_packs(),
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key:PhysicalKey::Code(KeyCode::F3),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => self.cycle_msaa(),
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key:PhysicalKey::Code(KeyCode::F4),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => self.cycle_present_mode(),
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key:PhysicalKey::Code(KeyCode::F10),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => self.toggle_post_process(|p| p.tonemapping = !p.tonemapping),
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key:PhysicalKey::Code(KeyCode::F11),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => self.toggle_post_process(|p| p.fxaa = !p.fxaa),
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key:PhysicalKey::Code(KeyCode::F2),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                // Shift+F2 for a high resolution screenshot
+                let scale = if self.modifiers.shift_key() { self.settings.graphics.screenshot_scale } else { 1 };
+                self.renderer.request_screenshot(scale);
+            }
             
             _ => {}
         }
@@ -142,7 +307,31 @@ impl Game {
         
     }
 
+    /// Recompile the shaders when the watched shader directory changed. Compile errors
+    /// are logged and the previous pipelines stay in use.
+    fn reload_changed_shaders(&mut self) {
+        let changed = match self.shader_watcher.as_mut() {
+            Some(watcher) => watcher.poll(),
+            None => return,
+        };
+        if changed.is_empty() {
+            return;
+        }
+
+        println!("shaders changed: {:?}", changed);
+        let packs = self.active_resource_packs();
+        let reloaded = join_errors([
+            self.scene.reload_shaders(&self.renderer, &packs),
+            self.renderer.reload_shaders(&packs),
+        ]);
+        match reloaded {
+            Ok(()) => println!("shaders reloaded"),
+            Err(e) => eprintln!("Failed to reload shaders, keeping the previous ones: {:?}", e),
+        }
+    }
+
     pub fn update(&mut self, dt: std::time::Duration) {
+        self.reload_changed_shaders();
         self.scene.update(&mut self.renderer, dt);
         self.renderer.update()
     }
@@ -155,4 +344,23 @@ impl Game {
     }
 
     
-}
\ No newline at end of file
+}
+
+
+// Encode and write a captured frame on another thread, PNG compression of a large
+// screenshot would stall the frame.
+fn save_screenshot(screenshot: anyhow::Result<image::RgbaImage>) {
+    let image = match screenshot {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Failed to capture screenshot: {:?}", e);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        match screenshot::save(&image, std::path::Path::new(screenshot::SCREENSHOT_DIR)) {
+            Ok(path) => println!("screenshot saved to {}", path.display()),
+            Err(e) => eprintln!("Failed to save screenshot: {:?}", e),
+        }
+    });
+}
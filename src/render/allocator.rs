@@ -0,0 +1,163 @@
+use std::ops::Range;
+
+/// First-fit sub-allocator handing out element ranges of a fixed size buffer.
+/// It only does the bookkeeping, the owner of the buffer is in charge of moving
+/// the data around when the ranges change after a defragmentation.
+pub struct RangeAllocator {
+    capacity: usize,
+    // sorted by start and never adjacent, neighbours are merged on free
+    free: Vec<Range<usize>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            free: if capacity > 0 { vec![0..capacity] } else { vec![] },
+        }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    pub fn free_space(&self) -> usize {
+        self.free.iter().map(|r| r.len()).sum()
+    }
+
+    pub fn largest_free_block(&self) -> usize {
+        self.free.iter().map(|r| r.len()).max().unwrap_or(0)
+    }
+
+    /// Reserve `len` contiguous elements, `None` if no free block is large enough.
+    pub fn allocate(&mut self, len: usize) -> Option<Range<usize>> {
+        if len == 0 {
+            return None;
+        }
+
+        let i = self.free.iter().position(|r| r.len() >= len)?;
+        let start = self.free[i].start;
+        self.free[i].start += len;
+        if self.free[i].is_empty() {
+            self.free.remove(i);
+        }
+
+        Some(start..start + len)
+    }
+
+    /// Give a range obtained from `allocate` back to the allocator.
+    pub fn free(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        debug_assert!(range.end <= self.capacity, "range out of the allocator bounds");
+
+        let i = self.free.partition_point(|r| r.start < range.start);
+        debug_assert!(i == 0 || self.free[i - 1].end <= range.start, "double free");
+        debug_assert!(i == self.free.len() || range.end <= self.free[i].start, "double free");
+
+        let merges_prev = i > 0 && self.free[i - 1].end == range.start;
+        let merges_next = i < self.free.len() && self.free[i].start == range.end;
+
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                self.free[i - 1].end = self.free[i].end;
+                self.free.remove(i);
+            }
+            (true, false) => self.free[i - 1].end = range.end,
+            (false, true) => self.free[i].start = range.start,
+            (false, false) => self.free.insert(i, range),
+        }
+    }
+
+    /// Packs the given live allocations at the start of the buffer, keeping their order,
+    /// and leaves a single free block at the end. Returns the new range of every
+    /// allocation, in the same order they were passed.
+    pub fn defragment(&mut self, allocations: &[Range<usize>]) -> Vec<Range<usize>> {
+        let mut order: Vec<usize> = (0..allocations.len()).collect();
+        order.sort_by_key(|&i| allocations[i].start);
+
+        let mut packed = vec![0..0; allocations.len()];
+        let mut cursor = 0;
+        for i in order {
+            let len = allocations[i].len();
+            packed[i] = cursor..cursor + len;
+            cursor += len;
+        }
+
+        self.free.clear();
+        if cursor < self.capacity {
+            self.free.push(cursor..self.capacity);
+        }
+
+        packed
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_first_fit() {
+        let mut allocator = RangeAllocator::new(100);
+        assert_eq!(allocator.allocate(10), Some(0..10));
+        assert_eq!(allocator.allocate(20), Some(10..30));
+        assert_eq!(allocator.free_space(), 70);
+        assert_eq!(allocator.allocate(71), None);
+        assert_eq!(allocator.allocate(0), None);
+    }
+
+    #[test]
+    fn reuses_freed_ranges() {
+        let mut allocator = RangeAllocator::new(100);
+        let a = allocator.allocate(10).unwrap();
+        let _b = allocator.allocate(10).unwrap();
+        allocator.free(a);
+        assert_eq!(allocator.allocate(5), Some(0..5));
+        assert_eq!(allocator.allocate(5), Some(5..10));
+        assert_eq!(allocator.allocate(5), Some(20..25));
+    }
+
+    #[test]
+    fn coalesces_neighbouring_blocks() {
+        let mut allocator = RangeAllocator::new(30);
+        let a = allocator.allocate(10).unwrap();
+        let b = allocator.allocate(10).unwrap();
+        let c = allocator.allocate(10).unwrap();
+        assert_eq!(allocator.largest_free_block(), 0);
+
+        allocator.free(a);
+        allocator.free(c);
+        assert_eq!(allocator.largest_free_block(), 10);
+
+        allocator.free(b);
+        assert_eq!(allocator.largest_free_block(), 30);
+        assert_eq!(allocator.allocate(30), Some(0..30));
+    }
+
+    #[test]
+    fn defragment_packs_allocations() {
+        let mut allocator = RangeAllocator::new(40);
+        let a = allocator.allocate(10).unwrap();
+        let b = allocator.allocate(10).unwrap();
+        let c = allocator.allocate(10).unwrap();
+        allocator.free(a);
+        allocator.free(c.clone());
+        let d = allocator.allocate(5).unwrap();
+        assert_eq!(allocator.allocate(25), None);
+
+        let packed = allocator.defragment(&[b, d]);
+        assert_eq!(packed, vec![5..15, 0..5]);
+        assert_eq!(allocator.free_space(), 25);
+        assert_eq!(allocator.allocate(25), Some(15..40));
+    }
+
+    #[test]
+    fn defragment_of_full_allocator_leaves_no_free_block() {
+        let mut allocator = RangeAllocator::new(20);
+        let a = allocator.allocate(20).unwrap();
+        assert_eq!(allocator.defragment(&[a]), vec![0..20]);
+        assert_eq!(allocator.free_space(), 0);
+        assert_eq!(allocator.allocate(1), None);
+    }
+}
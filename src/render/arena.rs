@@ -0,0 +1,207 @@
+use std::ops::Range;
+
+use bytemuck::{Pod, Zeroable};
+
+use super::{allocator::RangeAllocator, buffer::DynamicBuffer, mesh::Mesh, Vertex};
+
+
+/// Arguments of one indexed indirect draw, laid out as `draw_indexed_indirect` expects them.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+#[derive(Clone, Default)]
+struct ArenaSlot {
+    vertices: Range<usize>,
+    indices: Range<usize>,
+    num_indices: usize,
+}
+
+/// One large vertex and index buffer pair shared by many meshes. Every mesh lives in a
+/// slot, indices stay relative to the slot so they are drawn with a base vertex, and
+/// the draw arguments of all the slots are kept in an indirect buffer.
+pub struct MeshArena<V: Vertex> {
+    vbuf: DynamicBuffer<V>,
+    ibuf: DynamicBuffer<u16>,
+    draws: DynamicBuffer<DrawIndexedIndirectArgs>,
+    vertex_allocator: RangeAllocator,
+    index_allocator: RangeAllocator,
+    slots: Vec<ArenaSlot>,
+}
+
+impl<V: Vertex> MeshArena<V> {
+    pub fn new(device: &wgpu::Device, num_slots: usize, vertex_capacity: usize, index_capacity: usize) -> Self {
+        // index ranges are kept even so every copy stays 4 bytes aligned
+        let index_capacity = index_capacity + index_capacity % 2;
+        Self {
+            vbuf: DynamicBuffer::new(device, vertex_capacity, wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC),
+            ibuf: DynamicBuffer::new(device, index_capacity, wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC),
+            draws: DynamicBuffer::new(device, num_slots, wgpu::BufferUsages::INDIRECT),
+            vertex_allocator: RangeAllocator::new(vertex_capacity),
+            index_allocator: RangeAllocator::new(index_capacity),
+            slots: vec![ArenaSlot::default(); num_slots],
+        }
+    }
+
+    pub fn num_slots(&self) -> usize { self.slots.len() }
+
+    pub fn free_vertices(&self) -> usize { self.vertex_allocator.free_space() }
+
+    /// Replace the mesh held by `slot`. When the arena is too fragmented to fit it, the
+    /// live meshes are compacted first. Returns `false` if the mesh doesn't fit at all,
+    /// leaving the slot empty.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, slot: usize, mesh: &Mesh<V>) -> bool {
+        self.release(slot);
+
+        let num_indices = mesh.indices().len();
+        if mesh.vertices().is_empty() || num_indices == 0 {
+            self.write_draw(queue, slot);
+            return true;
+        }
+
+        let index_len = num_indices + num_indices % 2;
+        if self.vertex_allocator.largest_free_block() < mesh.vertices().len()
+            || self.index_allocator.largest_free_block() < index_len
+        {
+            self.defragment(device, queue);
+        }
+
+        let vertices = self.vertex_allocator.allocate(mesh.vertices().len());
+        let indices = self.index_allocator.allocate(index_len);
+        let (vertices, indices) = match (vertices, indices) {
+            (Some(vertices), Some(indices)) => (vertices, indices),
+            (vertices, indices) => {
+                if let Some(vertices) = vertices {
+                    self.vertex_allocator.free(vertices);
+                }
+                if let Some(indices) = indices {
+                    self.index_allocator.free(indices);
+                }
+                self.write_draw(queue, slot);
+                return false;
+            }
+        };
+
+        self.vbuf.update(queue, mesh.vertices(), vertices.start);
+        if num_indices % 2 == 0 {
+            self.ibuf.update(queue, mesh.indices(), indices.start);
+        } else {
+            let mut padded = mesh.indices().to_vec();
+            padded.push(0);
+            self.ibuf.update(queue, &padded, indices.start);
+        }
+
+        self.slots[slot] = ArenaSlot { vertices, indices, num_indices };
+        self.write_draw(queue, slot);
+        true
+    }
+
+    /// Empty a slot and give its memory back to the arena.
+    pub fn clear(&mut self, queue: &wgpu::Queue, slot: usize) {
+        self.release(slot);
+        self.write_draw(queue, slot);
+    }
+
+    fn release(&mut self, slot: usize) {
+        let old = std::mem::take(&mut self.slots[slot]);
+        self.vertex_allocator.free(old.vertices);
+        self.index_allocator.free(old.indices);
+    }
+
+    fn draw_args(&self, slot: usize) -> DrawIndexedIndirectArgs {
+        let s = &self.slots[slot];
+        DrawIndexedIndirectArgs {
+            index_count: s.num_indices as u32,
+            instance_count: if s.num_indices > 0 { 1 } else { 0 },
+            first_index: s.indices.start as u32,
+            base_vertex: s.vertices.start as i32,
+            first_instance: 0,
+        }
+    }
+
+    fn write_draw(&self, queue: &wgpu::Queue, slot: usize) {
+        self.draws.update(queue, &[self.draw_args(slot)], slot);
+    }
+
+    /// Move every live mesh to the start of the buffers, going through a temporary
+    /// buffer since a buffer can't be copied onto itself.
+    fn defragment(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let vertex_ranges: Vec<Range<usize>> = self.slots.iter().map(|s| s.vertices.clone()).collect();
+        let index_ranges: Vec<Range<usize>> = self.slots.iter().map(|s| s.indices.clone()).collect();
+        let packed_vertices = self.vertex_allocator.defragment(&vertex_ranges);
+        let packed_indices = self.index_allocator.defragment(&index_ranges);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mesh arena defragmentation"),
+        });
+        compact::<V>(device, &mut encoder, &self.vbuf.buff, &vertex_ranges, &packed_vertices);
+        compact::<u16>(device, &mut encoder, &self.ibuf.buff, &index_ranges, &packed_indices);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        for (slot, (vertices, indices)) in self.slots.iter_mut().zip(packed_vertices.into_iter().zip(packed_indices)) {
+            slot.vertices = vertices;
+            slot.indices = indices;
+        }
+        let draws: Vec<DrawIndexedIndirectArgs> = (0..self.slots.len()).map(|i| self.draw_args(i)).collect();
+        self.draws.update(queue, &draws, 0);
+    }
+
+    /// Draw a range of slots with a single `multi_draw_indexed_indirect` call, or one
+    /// `draw_indexed` per slot when the adapter lacks `MULTI_DRAW_INDIRECT`.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, slots: Range<usize>, multi_draw: bool) {
+        if multi_draw {
+            self.bind_buffers(render_pass);
+            let stride = std::mem::size_of::<DrawIndexedIndirectArgs>() as u64;
+            render_pass.multi_draw_indexed_indirect(&self.draws.buff, slots.start as u64 * stride, slots.len() as u32);
+        } else {
+            self.draw_ordered(render_pass, &slots.collect::<Vec<_>>());
+        }
+    }
+
+    /// Draw the given slots one by one, in order.
+    pub fn draw_ordered<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, slots: &[usize]) {
+        self.bind_buffers(render_pass);
+        for slot in slots.iter().map(|&i| &self.slots[i]).filter(|s| s.num_indices > 0) {
+            let first_index = slot.indices.start as u32;
+            render_pass.draw_indexed(first_index..first_index + slot.num_indices as u32, slot.vertices.start as i32, 0..1);
+        }
+    }
+
+    fn bind_buffers<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vbuf.buff.slice(..));
+        render_pass.set_index_buffer(self.ibuf.buff.slice(..), wgpu::IndexFormat::Uint16);
+    }
+}
+
+
+fn compact<T: Pod>(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    buffer: &wgpu::Buffer,
+    old: &[Range<usize>],
+    new: &[Range<usize>],
+) {
+    let stride = std::mem::size_of::<T>() as u64;
+    let used = new.iter().map(|r| r.end).max().unwrap_or(0) as u64 * stride;
+    if used == 0 {
+        return;
+    }
+
+    let scratch = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Mesh arena scratch"),
+        size: used,
+        usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    for (old, new) in old.iter().zip(new).filter(|(r, _)| !r.is_empty()) {
+        encoder.copy_buffer_to_buffer(buffer, old.start as u64 * stride, &scratch, new.start as u64 * stride, old.len() as u64 * stride);
+    }
+    encoder.copy_buffer_to_buffer(&scratch, 0, buffer, 0, used);
+}
@@ -1,9 +1,15 @@
 use anyhow::*;
 
+use crate::render::atlas_builder::{AtlasBuilder, AtlasLayout, ATLAS_GUTTER};
+use crate::render::buffer::Buffer;
+use crate::render::texture_array::TextureArrayLayout;
+use crate::resource_pack::ResourcePacks;
 use crate::render::texture::*;
 use crate::scene::terrain::block::*;
+use crate::settings::GraphicsSettings;
+use crate::scene::terrain::light::MAX_LIGHT;
 
-use super::pipelines::GlobalsLayouts;
+use super::pipelines::{terrain::RenderLayer, GlobalsLayouts};
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -12,78 +18,354 @@ pub enum MaterialType {
     GRASS,
     ROCK,
     WATER,
+    LEAVES,
+    GLASS,
+    LAVA,
+    PORTAL,
     AIR,
     DEBUG,
 }
 
 impl MaterialType {
-    pub fn get_texture_coordinates(&self, texture_corner: [u32; 2], quad_side: QuadSide) -> [f32; 2] {
+    /// Layer the faces of this material are drawn in. Translucent materials are blended
+    /// with the alpha of their texture, which is why water uses the see-through water
+    /// texture (tile (14, 0) of the old atlas) rather than the opaque one at (13, 0).
+    pub fn render_layer(&self) -> RenderLayer {
         match self {
-            MaterialType::GRASS => match quad_side {
-                QuadSide::TOP => atlas_pos_to_coordinates([0.0, 0.0], texture_corner),
-                QuadSide::BOTTOM => atlas_pos_to_coordinates([2.0, 0.0], texture_corner),
-                QuadSide::RIGHT => atlas_pos_to_coordinates([3.0, 0.0], texture_corner),
-                QuadSide::LEFT => atlas_pos_to_coordinates([3.0, 0.0], texture_corner),
-                QuadSide::FRONT => atlas_pos_to_coordinates([3.0, 0.0], texture_corner),
-                QuadSide::BACK => atlas_pos_to_coordinates([3.0, 0.0], texture_corner),
-            },
-            MaterialType::DIRT => atlas_pos_to_coordinates([2.0, 0.0], texture_corner),
-            MaterialType::ROCK => atlas_pos_to_coordinates([1.0, 0.0], texture_corner),
-            MaterialType::WATER => atlas_pos_to_coordinates([13.0, 0.0], texture_corner),
-            MaterialType::AIR => [0.0, 0.0],
-            MaterialType::DEBUG => atlas_pos_to_coordinates([15.0, 3.0], texture_corner),
+            MaterialType::WATER | MaterialType::PORTAL => RenderLayer::TRANSLUCENT,
+            MaterialType::LEAVES | MaterialType::GLASS => RenderLayer::CUTOUT,
+            _ => RenderLayer::OPAQUE,
+        }
+    }
+
+    /// Whether a face of this material is hidden by the block next to it. Only opaque
+    /// blocks hide everything, see-through ones just hide faces of their own material
+    /// so water bodies and glass panes don't render their inner faces.
+    pub fn face_hidden_by(&self, neighbor: MaterialType) -> bool {
+        match neighbor {
+            MaterialType::AIR => false,
+            _ if neighbor.render_layer() == RenderLayer::OPAQUE => true,
+            _ => neighbor == *self,
+        }
+    }
+
+    /// Block light level this material emits, 0 for blocks that don't glow.
+    pub fn light_emission(&self) -> u8 {
+        match self {
+            MaterialType::LAVA => MAX_LIGHT,
+            MaterialType::PORTAL => 11,
+            _ => 0,
+        }
+    }
+
+    /// Whether light spreads through blocks of this material.
+    pub fn transmits_light(&self) -> bool {
+        *self == MaterialType::AIR || self.render_layer() != RenderLayer::OPAQUE
+    }
+
+    /// Name used for the material in block definitions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaterialType::DIRT => "dirt",
+            MaterialType::GRASS => "grass",
+            MaterialType::ROCK => "rock",
+            MaterialType::WATER => "water",
+            MaterialType::LEAVES => "leaves",
+            MaterialType::GLASS => "glass",
+            MaterialType::LAVA => "lava",
+            MaterialType::PORTAL => "portal",
+            MaterialType::AIR => "air",
+            MaterialType::DEBUG => "debug",
         }
     }
 }
 
-const BLOCK_PIXEL_SIZE: f32 = 16.0;
-const ATLAS_PIXEL_SIZE: f32 = 256.0;
 
-fn atlas_pos_to_coordinates(atlas_pos: [f32; 2], texture_corner: [u32; 2]) -> [f32; 2] {
-    let mut pixel_x = atlas_pos[0] * BLOCK_PIXEL_SIZE;
-    let mut pixel_y = atlas_pos[1] * BLOCK_PIXEL_SIZE;
+/// Texture used in place of the ones missing from the resource packs.
+pub const MISSING_TEXTURE: &str = "debug";
+
+pub(crate) const MATERIALS: [MaterialType; 10] = [
+    MaterialType::DIRT,
+    MaterialType::GRASS,
+    MaterialType::ROCK,
+    MaterialType::WATER,
+    MaterialType::LEAVES,
+    MaterialType::GLASS,
+    MaterialType::LAVA,
+    MaterialType::PORTAL,
+    MaterialType::AIR,
+    MaterialType::DEBUG,
+];
+
+const QUAD_SIDES: [QuadSide; 6] = [QuadSide::TOP, QuadSide::BOTTOM, QuadSide::RIGHT, QuadSide::LEFT, QuadSide::FRONT, QuadSide::BACK];
 
-    if texture_corner[0] == 1 {
-        pixel_x += 15.0;
+
+/// Textures of the faces of a block, from `blocks.json`. The side specific entries
+/// take precedence over `all`.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct BlockFaces {
+    pub all: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub side: Option<String>,
+}
+
+/// Texture names of every material, keyed by `MaterialType::name`.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct BlockDefinitions(pub std::collections::HashMap<String, BlockFaces>);
+
+impl BlockDefinitions {
+    pub fn texture(&self, material_type: MaterialType, quad_side: QuadSide) -> &str {
+        let faces = match self.0.get(material_type.name()) {
+            Some(faces) => faces,
+            None => return MISSING_TEXTURE,
+        };
+        let side = match quad_side {
+            QuadSide::TOP => &faces.top,
+            QuadSide::BOTTOM => &faces.bottom,
+            _ => &faces.side,
+        };
+        side.as_deref().or(faces.all.as_deref()).unwrap_or(MISSING_TEXTURE)
     }
+}
+
+
+/// Playback of an animated texture, from `animations.json`.
+#[derive(Copy, Clone, Debug, serde::Deserialize)]
+pub struct TextureAnimation {
+    /// Seconds each frame stays on screen.
+    pub frame_time: f32,
+}
+
+impl Default for TextureAnimation {
+    fn default() -> Self {
+        Self { frame_time: 0.1 }
+    }
+}
+
+/// Animations keyed by texture name. Textures made of several frames without an
+/// entry play at the default frame time.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct TextureAnimations(pub std::collections::HashMap<String, TextureAnimation>);
+
+
+/// Layers the block texture array can hold, the size of the animation uniform array.
+pub const MAX_BLOCK_TEXTURE_LAYERS: usize = 256;
+
+/// Animation of a texture array layer as the terrain shader reads it. Meshes reference
+/// the first frame and the shader offsets it by the frame to show at the game time.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LayerAnimation {
+    frame_count: u32,
+    frame_time: f32,
+    _padding: [u32; 2],
+}
 
-    if texture_corner[1] == 1 {
-        pixel_y += 16.0;
+fn layer_animations(layout: &TextureArrayLayout, animations: &TextureAnimations) -> Vec<LayerAnimation> {
+    let mut layers = vec![LayerAnimation::default(); MAX_BLOCK_TEXTURE_LAYERS];
+    for name in layout.layers.keys() {
+        let frame_count = layout.frame_count(name);
+        if frame_count > 1 {
+            let animation = animations.0.get(name).copied().unwrap_or_default();
+            layers[layout.layer(name).unwrap() as usize] = LayerAnimation {
+                frame_count,
+                frame_time: animation.frame_time.max(f32::EPSILON),
+                _padding: [0; 2],
+            };
+        }
     }
+    layers
+}
+
+// Longest animation period, game time is wrapped at it to keep the precision of an f32
+// in the shader. An animation skipping a frame once an hour goes unnoticed.
+const MAX_ANIMATION_PERIOD_MS: u64 = 3_600_000;
+
+// Seconds after which every animation is back at its first frame, the least common
+// multiple of their loop lengths. Counted in milliseconds so frame times like 0.15
+// divide it.
+fn animation_period(layers: &[LayerAnimation]) -> f64 {
+    let gcd = |mut a: u64, mut b: u64| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    let millis = layers.iter()
+        .filter(|animation| animation.frame_count > 1)
+        .map(|animation| (animation.frame_time as f64 * 1000.0).round().max(1.0) as u64 * animation.frame_count as u64)
+        .fold(1, |period, length| (period / gcd(period, length)).saturating_mul(length).min(MAX_ANIMATION_PERIOD_MS));
+    millis as f64 / 1000.0
+}
+
 
-    return [pixel_x / ATLAS_PIXEL_SIZE, pixel_y / ATLAS_PIXEL_SIZE];
+fn resolve_face_layers(layout: &TextureArrayLayout, definitions: &BlockDefinitions) -> Vec<[u32; 6]> {
+    MATERIALS.iter().map(|&material_type| {
+        QUAD_SIDES.map(|side| {
+            let name = definitions.texture(material_type, side);
+            layout.layer(name).or_else(|| layout.layer(MISSING_TEXTURE)).unwrap_or(0)
+        })
+    }).collect()
 }
 
-pub struct Atlas {
+/// Every block texture, one layer per frame, in a single texture array. With the atlas
+/// setting every layer is a tile of a 2D atlas instead, the layer indexing the uniform
+/// array of atlas regions the shader wraps its UVs into.
+pub struct BlockTextures {
     pub texture: Texture,
+    pub animations: Buffer<LayerAnimation>,
     pub bind_group: wgpu::BindGroup,
-
+    pub layout: TextureArrayLayout,
+    /// Where every layer is packed when the textures are an atlas, named by layer index.
+    pub atlas: Option<AtlasLayout>,
+    /// UV offset and size of every layer in the atlas, bound after the animations.
+    pub regions: Option<Buffer<[f32; 4]>>,
+    /// Seconds after which every animation starts over.
+    pub animation_period: f64,
+    // layer of every face of every material, indexed by material then quad side
+    face_layers: Vec<[u32; 6]>,
 }
 
-impl Atlas {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, layouts: &GlobalsLayouts) -> Result<Self> {
+impl BlockTextures {
+    /// Upload the block textures of the resource packs. Meshes built from other block
+    /// textures point at their layers and have to be rebuilt. The
+    /// settings choose between the texture array and the atlas, and their anisotropic
+    /// filtering.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &GlobalsLayouts,
+        packs: &ResourcePacks,
+        settings: &GraphicsSettings,
+    ) -> Result<Self> {
 
-        let diffuse_bytes = include_bytes!("../../assets/images/textures_atlas.png");
-        let texture = Texture::from_bytes(&device, &queue, diffuse_bytes, "blocks.png").unwrap();
+        let definitions = packs.block_definitions()?;
+        let builder = packs.block_textures()?;
+        let (texture, layout, atlas) = if settings.texture_atlas {
+            let (frames, layout) = builder.frames()?;
+            ensure!(
+                layout.layer_count as usize <= MAX_BLOCK_TEXTURE_LAYERS,
+                "{} block texture layers, at most {} are supported", layout.layer_count, MAX_BLOCK_TEXTURE_LAYERS
+            );
+            let mut atlas_builder = AtlasBuilder::new(ATLAS_GUTTER);
+            for (layer, frame) in frames.into_iter().enumerate() {
+                atlas_builder.add(&layer.to_string(), frame)?;
+            }
+            let (levels, atlas) = atlas_builder.build()?;
+            println!(
+                "packed {} block textures in {} tiles of a {}x{} atlas with {} mip levels",
+                layout.layers.len(), layout.layer_count, atlas.width, atlas.height, atlas.mip_levels
+            );
+            let max_size = device.limits().max_texture_dimension_2d;
+            ensure!(
+                atlas.width <= max_size && atlas.height <= max_size,
+                "block atlas is {}x{}, at most {max_size} pixels a side are supported", atlas.width, atlas.height
+            );
+            let texture = Texture::from_mip_chain(device, queue, &levels, Some("block atlas"), settings.anisotropy)?;
+            (texture, layout, Some(atlas))
+        } else {
+            let (layers, layout) = builder.build()?;
+            println!(
+                "loaded {} block textures in {} layers of {}x{} with {} mip levels",
+                layout.layers.len(), layout.layer_count, layout.size, layout.size, layout.mip_levels
+            );
+            let max_layers = (device.limits().max_texture_array_layers as usize).min(MAX_BLOCK_TEXTURE_LAYERS);
+            ensure!(
+                layout.layer_count as usize <= max_layers,
+                "{} block texture layers, at most {} are supported", layout.layer_count, max_layers
+            );
+            let texture = Texture::from_layers(device, queue, &layers, Some("block textures"), settings.anisotropy)?;
+            (texture, layout, None)
+        };
 
+        let layer_animations = layer_animations(&layout, &packs.texture_animations()?);
+        let animations = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &layer_animations);
+        let regions = atlas.as_ref().map(|atlas| Buffer::new(
+            device,
+            wgpu::BufferUsages::UNIFORM,
+            &atlas_regions(atlas),
+        ));
+        let face_layers = resolve_face_layers(&layout, &definitions);
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: animations.buff.as_entire_binding(),
+            },
+        ];
+        if let Some(regions) = &regions {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 3,
+                resource: regions.buff.as_entire_binding(),
+            });
+        }
+        let bind_group_layout = if atlas.is_some() { &layouts.atlas_layout } else { &layouts.texture_array_layout };
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &layouts.atlas_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
+            layout: bind_group_layout,
+            entries: &entries,
+            label: Some("block_textures_bind_group"),
         });
 
         Ok(Self {
             texture,
+            animations,
             bind_group,
+            layout,
+            atlas,
+            regions,
+            animation_period: animation_period(&layer_animations),
+            face_layers,
         })
     }
+
+    /// Layer the mesher gives a face of a material.
+    pub fn face_layer(&self, material_type: MaterialType, quad_side: QuadSide) -> u32 {
+        self.face_layers.get(material_type as usize).map_or(0, |layers| layers[quad_side as usize])
+    }
+
+    /// Layout of `bind_group`, for the pipelines drawing with these textures.
+    pub fn bind_group_layout<'a>(&self, layouts: &'a GlobalsLayouts) -> &'a wgpu::BindGroupLayout {
+        if self.atlas.is_some() { &layouts.atlas_layout } else { &layouts.texture_array_layout }
+    }
+
+    /// Defines the terrain shaders have to be compiled with to sample these textures.
+    pub fn shader_defines(&self) -> &'static [(&'static str, &'static str)] {
+        if self.atlas.is_some() { &[("TEXTURE_ATLAS", "")] } else { &[] }
+    }
+}
+
+
+// UV offset and size of every layer in the atlas, unused layers cover the whole atlas
+fn atlas_regions(atlas: &AtlasLayout) -> Vec<[f32; 4]> {
+    (0..MAX_BLOCK_TEXTURE_LAYERS)
+        .map(|layer| atlas.uv_rect(&layer.to_string()).unwrap_or([0.0, 0.0, 1.0, 1.0]))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(frame_count: u32, frame_time: f32) -> LayerAnimation {
+        LayerAnimation { frame_count, frame_time, _padding: [0; 2] }
+    }
+
+    #[test]
+    fn animations_loop_together_after_the_period() {
+        assert_eq!(animation_period(&[]), 0.001);
+        assert_eq!(animation_period(&[animation(0, 0.0), animation(8, 0.15)]), 1.2);
+        assert_eq!(animation_period(&[animation(8, 0.15), animation(4, 0.1), animation(3, 0.25)]), 6.0);
+        // loops that only line up after ages are cut short
+        assert_eq!(animation_period(&[animation(7, 0.997), animation(11, 1.013), animation(13, 0.331)]), 3600.0);
+    }
 }
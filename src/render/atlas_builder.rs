@@ -0,0 +1,299 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::*;
+use image::RgbaImage;
+
+use super::texture_array::downsample;
+
+
+/// Pixels of padding around every tile. Gutters repeat the border texels of their tile,
+/// so sampling close to a tile edge never reads from the tile next to it. It is a power
+/// of two so tiles stay aligned on every mip level, each level halving the gutter.
+pub const ATLAS_GUTTER: u32 = 8;
+
+
+/// Pixel rectangle of a texture inside the atlas, gutters excluded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Where every texture ended up once packed.
+#[derive(Clone, Debug, Default)]
+pub struct AtlasLayout {
+    pub width: u32,
+    pub height: u32,
+    /// Mip levels generated, limited so every tile keeps at least one gutter texel.
+    pub mip_levels: u32,
+    pub regions: HashMap<String, AtlasRegion>,
+}
+
+impl AtlasLayout {
+    /// UV coordinates of a corner of the named texture, `corner` being 0 or 1 on each axis.
+    pub fn uv(&self, name: &str, corner: [u32; 2]) -> Option<[f32; 2]> {
+        let region = self.regions.get(name)?;
+        let x = region.x + corner[0] * region.width;
+        let y = region.y + corner[1] * region.height;
+        Some([x as f32 / self.width as f32, y as f32 / self.height as f32])
+    }
+
+    /// UV offset (xy) and size (zw) of the named texture.
+    pub fn uv_rect(&self, name: &str) -> Option<[f32; 4]> {
+        let [u0, v0] = self.uv(name, [0, 0])?;
+        let [u1, v1] = self.uv(name, [1, 1])?;
+        Some([u0, v0, u1 - u0, v1 - v0])
+    }
+}
+
+
+/// Packs individual textures into a single atlas image.
+pub struct AtlasBuilder {
+    gutter: u32,
+    textures: Vec<(String, RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(gutter: u32) -> Self {
+        Self { gutter, textures: Vec::new() }
+    }
+
+    /// Add a texture, replacing any previous one with the same name. Both sides have to
+    /// be powers of two.
+    pub fn add(&mut self, name: &str, image: RgbaImage) -> Result<()> {
+        let (width, height) = image.dimensions();
+        ensure!(
+            width.is_power_of_two() && height.is_power_of_two(),
+            "texture {name} is {width}x{height}, sides must be powers of two"
+        );
+
+        self.textures.retain(|(n, _)| n != name);
+        self.textures.push((name.to_string(), image));
+        Ok(())
+    }
+
+    pub fn add_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        let image = image::load_from_memory(bytes)
+            .with_context(|| format!("failed to decode texture {name}"))?;
+        self.add(name, image.to_rgba8())
+    }
+
+    /// Add every png of a directory, named after their file stem. Returns how many were added.
+    pub fn add_dir(&mut self, dir: &Path) -> Result<usize> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read texture directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "png"))
+            .collect();
+        paths.sort();
+
+        for path in &paths {
+            let name = path.file_stem().and_then(|s| s.to_str())
+                .with_context(|| format!("invalid texture name {}", path.display()))?;
+            let image = image::open(path)
+                .with_context(|| format!("failed to load texture {}", path.display()))?;
+            self.add(name, image.to_rgba8())?;
+        }
+
+        Ok(paths.len())
+    }
+
+    /// Shelf-pack the textures, tallest first, into the smallest power of two atlas
+    /// that is at least as wide as it is tall. Returns every mip level of the atlas,
+    /// each tile being downsampled on its own so levels never bleed between tiles.
+    pub fn build(self) -> Result<(Vec<RgbaImage>, AtlasLayout)> {
+        ensure!(!self.textures.is_empty(), "no textures to pack in the atlas");
+
+        let gutter = self.gutter;
+        let padded = |image: &RgbaImage| (image.width() + 2 * gutter, image.height() + 2 * gutter);
+
+        let mut order: Vec<usize> = (0..self.textures.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (ta, tb) = (&self.textures[a], &self.textures[b]);
+            tb.1.height().cmp(&ta.1.height())
+                .then(tb.1.width().cmp(&ta.1.width()))
+                .then(ta.0.cmp(&tb.0))
+        });
+
+        let area: u64 = self.textures.iter().map(|(_, image)| {
+            let (w, h) = padded(image);
+            w as u64 * h as u64
+        }).sum();
+        let widest = self.textures.iter().map(|(_, image)| padded(image).0).max().unwrap_or(1);
+        let mut width = ((area as f64).sqrt().ceil() as u32).max(widest).next_power_of_two();
+
+        let (positions, height) = loop {
+            let (positions, used_height) = shelf_pack(&order, &self.textures, width, padded);
+            let height = used_height.next_power_of_two();
+            if height <= width {
+                break (positions, height);
+            }
+            width *= 2;
+        };
+
+        // a level is usable while gutters and tiles are still at least one texel
+        let smallest_side = self.textures.iter().map(|(_, image)| image.width().min(image.height())).min().unwrap_or(1);
+        let mip_levels = if gutter.is_power_of_two() {
+            gutter.trailing_zeros().min(smallest_side.trailing_zeros()) + 1
+        } else {
+            1
+        };
+
+        let mut layout = AtlasLayout { width, height, mip_levels, regions: HashMap::new() };
+        for (&i, &(x, y)) in order.iter().zip(positions.iter()) {
+            let (name, image) = &self.textures[i];
+            layout.regions.insert(name.clone(), AtlasRegion {
+                x: x + gutter,
+                y: y + gutter,
+                width: image.width(),
+                height: image.height(),
+            });
+        }
+
+        let mut tiles: Vec<RgbaImage> = order.iter().map(|&i| self.textures[i].1.clone()).collect();
+        let mut levels = Vec::with_capacity(mip_levels as usize);
+        for level in 0..mip_levels {
+            if level > 0 {
+                tiles = tiles.iter().map(downsample).collect();
+            }
+
+            let mut atlas = RgbaImage::new(width >> level, height >> level);
+            for (tile, &(x, y)) in tiles.iter().zip(positions.iter()) {
+                blit_with_gutter(&mut atlas, tile, x >> level, y >> level, gutter >> level);
+            }
+            levels.push(atlas);
+        }
+
+        Ok((levels, layout))
+    }
+}
+
+
+// Copy a tile at (x, y) surrounded by `gutter` texels clamped to its closest border texel.
+fn blit_with_gutter(atlas: &mut RgbaImage, tile: &RgbaImage, x: u32, y: u32, gutter: u32) {
+    for dy in 0..tile.height() + 2 * gutter {
+        for dx in 0..tile.width() + 2 * gutter {
+            let sx = dx.saturating_sub(gutter).min(tile.width() - 1);
+            let sy = dy.saturating_sub(gutter).min(tile.height() - 1);
+            atlas.put_pixel(x + dx, y + dy, *tile.get_pixel(sx, sy));
+        }
+    }
+}
+
+
+// Returns the top-left corner of every padded texture, in `order`, and the used height.
+fn shelf_pack(
+    order: &[usize],
+    textures: &[(String, RgbaImage)],
+    width: u32,
+    padded: impl Fn(&RgbaImage) -> (u32, u32),
+) -> (Vec<(u32, u32)>, u32) {
+    let mut positions = Vec::with_capacity(order.len());
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+
+    for &i in order {
+        let (w, h) = padded(&textures[i].1);
+        if x + w > width {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        positions.push((x, y));
+        x += w;
+        shelf_height = shelf_height.max(h);
+    }
+
+    (positions, y + shelf_height)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, image::Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn shelves_wrap_below_their_tallest_texture() {
+        let textures: Vec<(String, RgbaImage)> = [(16, 32), (16, 16), (16, 16), (32, 8)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| (i.to_string(), solid(w, h, 0)))
+            .collect();
+        let padded = |image: &RgbaImage| (image.width() + 2, image.height() + 2);
+
+        let (positions, height) = shelf_pack(&[0, 1, 2, 3], &textures, 60, padded);
+        assert_eq!(positions, vec![(0, 0), (18, 0), (36, 0), (0, 34)]);
+        assert_eq!(height, 34 + 10);
+
+        // a shelf is as tall as its tallest texture
+        let (positions, height) = shelf_pack(&[3, 0, 1], &textures, 40, padded);
+        assert_eq!(positions, vec![(0, 0), (0, 10), (18, 10)]);
+        assert_eq!(height, 10 + 34);
+    }
+
+    #[test]
+    fn gutters_repeat_the_closest_border_texel() {
+        let tile = RgbaImage::from_fn(2, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+        let mut atlas = RgbaImage::new(8, 8);
+        blit_with_gutter(&mut atlas, &tile, 1, 1, 2);
+
+        for y in 0..6 {
+            for x in 0..6 {
+                let expected = tile.get_pixel(x.max(2).min(3) - 2, y.max(2).min(3) - 2);
+                assert_eq!(atlas.get_pixel(x + 1, y + 1), expected, "texel {x}, {y}");
+            }
+        }
+        // nothing is written outside the padded tile
+        assert_eq!(atlas.get_pixel(0, 0).0, [0; 4]);
+        assert_eq!(atlas.get_pixel(7, 7).0, [0; 4]);
+    }
+
+    #[test]
+    fn mip_levels_never_bleed_between_tiles() {
+        let mut builder = AtlasBuilder::new(ATLAS_GUTTER);
+        builder.add("black", solid(16, 16, 0)).unwrap();
+        builder.add("white", solid(16, 16, 255)).unwrap();
+        builder.add("tall", solid(16, 32, 128)).unwrap();
+        let (levels, layout) = builder.build().unwrap();
+
+        // the 8 texel gutter halves down to a single texel at the fourth level
+        assert_eq!(layout.mip_levels, 4);
+        assert_eq!(levels.len(), 4);
+        assert!(layout.width.is_power_of_two() && layout.height.is_power_of_two());
+        assert!(layout.height <= layout.width);
+
+        for (name, value) in [("black", 0), ("white", 255), ("tall", 128)] {
+            let region = layout.regions[name];
+            for (level, image) in levels.iter().enumerate() {
+                let gutter = ATLAS_GUTTER >> level;
+                let (x0, y0) = ((region.x - ATLAS_GUTTER) >> level, (region.y - ATLAS_GUTTER) >> level);
+                let (w, h) = ((region.width >> level) + 2 * gutter, (region.height >> level) + 2 * gutter);
+                for y in y0..y0 + h {
+                    for x in x0..x0 + w {
+                        assert_eq!(image.get_pixel(x, y).0[0], value, "{name} at level {level}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uv_rects_cover_their_region() {
+        let mut builder = AtlasBuilder::new(ATLAS_GUTTER);
+        builder.add("a", solid(16, 16, 0)).unwrap();
+        let (_, layout) = builder.build().unwrap();
+        let region = layout.regions["a"];
+
+        let [u, v, width, height] = layout.uv_rect("a").unwrap();
+        assert_eq!(u, region.x as f32 / layout.width as f32);
+        assert_eq!(v, region.y as f32 / layout.height as f32);
+        assert_eq!(width, 16.0 / layout.width as f32);
+        assert_eq!(height, 16.0 / layout.height as f32);
+        assert!(layout.uv_rect("b").is_none());
+    }
+}
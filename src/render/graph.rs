@@ -0,0 +1,62 @@
+use wgpu::BindGroup;
+
+use super::renderer::Draw;
+
+
+/// Phases of a frame, in the order they are drawn. Shadow casters are drawn with
+/// `Draw::draw_shadow` once per cascade of the shadow map, the other phases share the
+/// scene pass and its depth, HDR and multisampled attachments before post processing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderPhase {
+    SHADOW,
+    /// Behind everything else, without depth testing.
+    BACKGROUND,
+    OPAQUE,
+    /// Blended over the opaque geometry.
+    TRANSLUCENT,
+    /// Drawn last, debug lines for instance.
+    OVERLAY,
+}
+
+impl RenderPhase {
+    pub const ALL: [RenderPhase; 5] = [
+        RenderPhase::SHADOW,
+        RenderPhase::BACKGROUND,
+        RenderPhase::OPAQUE,
+        RenderPhase::TRANSLUCENT,
+        RenderPhase::OVERLAY,
+    ];
+
+    /// Phases drawn in the scene pass.
+    pub const SCENE: [RenderPhase; 4] = [
+        RenderPhase::BACKGROUND,
+        RenderPhase::OPAQUE,
+        RenderPhase::TRANSLUCENT,
+        RenderPhase::OVERLAY,
+    ];
+}
+
+
+/// Everything a frame draws: the `Draw` implementors scene systems register to every
+/// phase, and the globals they are drawn with. It is built for every frame and handed
+/// to `Renderer::render`, which owns the passes and their attachments.
+pub struct RenderGraph<'a> {
+    pub globals: &'a BindGroup,
+    drawables: [Vec<Box<dyn Draw + 'a>>; RenderPhase::ALL.len()],
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new(globals: &'a BindGroup) -> Self {
+        Self { globals, drawables: Default::default() }
+    }
+
+    /// Draw `drawable` in `phase`, after whatever was added to it before.
+    pub fn add(&mut self, phase: RenderPhase, drawable: impl Draw + 'a) {
+        self.drawables[phase as usize].push(Box::new(drawable));
+    }
+
+    /// What is drawn in `phase`, in order.
+    pub fn phase(&self, phase: RenderPhase) -> impl Iterator<Item = &(dyn Draw + 'a)> {
+        self.drawables[phase as usize].iter().map(|drawable| drawable.as_ref())
+    }
+}
@@ -0,0 +1,80 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+
+// How often the shader directory is scanned, edits are rarely saved faster than this
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+
+/// Watches a directory of shaders for changes by polling their modification times.
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &Path) -> Self {
+        let mut watcher = Self {
+            dir: dir.to_path_buf(),
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.modified = watcher.scan();
+        watcher
+    }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    /// Shaders created, modified or removed since the last call, empty until the poll
+    /// interval has elapsed.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let modified = self.scan();
+        let mut changed: Vec<PathBuf> = modified.iter()
+            .filter(|(path, time)| self.modified.get(*path) != Some(*time))
+            .map(|(path, _)| path.clone())
+            .chain(self.modified.keys().filter(|path| !modified.contains_key(*path)).cloned())
+            .collect();
+        changed.sort();
+
+        self.modified = modified;
+        changed
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        // a directory that can't be read, e.g. while being replaced, just looks empty
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return HashMap::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "wgsl"))
+            .filter_map(|path| {
+                let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect()
+    }
+}
+
+
+/// Combine the results of reloading several parts, reporting every part that failed
+/// rather than only the first one.
+pub fn join_errors(results: impl IntoIterator<Item = anyhow::Result<()>>) -> anyhow::Result<()> {
+    let errors: Vec<String> = results.into_iter()
+        .filter_map(Result::err)
+        .map(|e| format!("{e:?}"))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(errors.join("\n\n")))
+    }
+}
@@ -1,4 +1,6 @@
 
+use cgmath::{MetricSpace, Point3};
+
 use crate::scene::terrain::chunk::{Blocks, pos_in_chunk_bounds, CHUNK_AREA, CHUNK_Y_SIZE};
 
 
@@ -93,7 +95,7 @@ impl<V: Vertex> Mesh<V>
 
                         if pos_in_chunk_bounds(neighbor_pos) {
                             let neighbor_block = blocks[neighbor_pos.y as usize][neighbor_pos.x as usize][neighbor_pos.z as usize].read().unwrap();
-                            if neighbor_block.material_type as u16 == MaterialType::AIR as u16 {
+                            if !block.material_type.face_hidden_by(neighbor_block.material_type) {
                                 visible = true;
                             }
 
@@ -123,7 +125,28 @@ impl<V: Vertex> Mesh<V>
 
 
 
-
-
-
-
+impl Mesh<BlockVertex> {
+    /// Reorder the quads so the ones furthest from `eye` are drawn first, translucent
+    /// geometry has to be blended back to front to look right. `eye` is relative to the
+    /// origin of the chunk, like the vertices.
+    pub fn sort_quads_back_to_front(&mut self, eye: Point3<f32>) {
+        let verts = &self.verts;
+        let center = |quad: &[u16]| {
+            let corners = [quad[0], quad[1], quad[2], quad[4]];
+            let sum = corners.iter().fold([0.0; 3], |acc, &i| {
+                let pos = verts[i as usize].pos().map(|c| c as f32);
+                [acc[0] + pos[0], acc[1] + pos[1], acc[2] + pos[2]]
+            });
+            Point3::new(sum[0] / 4.0, sum[1] / 4.0, sum[2] / 4.0)
+        };
+
+        let mut quads: Vec<(f32, &[u16])> = self.indices
+            .chunks_exact(6)
+            .map(|quad| (center(quad).distance2(eye), quad))
+            .collect();
+        quads.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let sorted: Vec<u16> = quads.into_iter().flat_map(|(_, quad)| quad.iter().copied()).collect();
+        self.indices = sorted;
+    }
+}
@@ -2,11 +2,21 @@ pub mod renderer;
 pub mod pipelines;
 pub mod texture;
 pub mod atlas;
+pub mod atlas_builder;
+pub mod texture_array;
 pub mod mesh;
 pub mod model;
 pub mod buffer;
 pub mod consts;
 pub mod binding;
+pub mod allocator;
+pub mod arena;
+pub mod hot_reload;
+pub mod preprocessor;
+pub mod readback;
+pub mod shadow;
+pub mod postprocess;
+pub mod graph;
 
 
 
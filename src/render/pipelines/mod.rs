@@ -1,11 +1,14 @@
 
 pub mod terrain;
+pub mod debug;
+pub mod sky;
+pub mod postprocess;
 
 use bytemuck::{Pod, Zeroable};
 use cgmath::{Matrix4, SquareMatrix};
 use wgpu::BindGroup;
 
-use super::{consts::Consts, texture::Texture};
+use super::{consts::Consts, shadow::ShadowMap, texture::Texture};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Zeroable, Pod)]
@@ -13,6 +16,23 @@ pub struct Globals {
     /// Transformation from world coordinate space (with focus_off as the
     /// origin) to the camera space
     view_proj: [[f32; 4]; 4],
+    /// Inverse of `view_proj`, turns screen positions back into view rays
+    inv_view_proj: [[f32; 4]; 4],
+    /// xyz: camera position in world space
+    eye_position: [f32; 4],
+    /// Debug visualisation toggles, x: colour terrain faces by their direction
+    debug_flags: [u32; 4],
+    /// x: game time in seconds, driving texture animations, y: time of day from 0 at
+    /// sunrise to 1 at the next one
+    time: [f32; 4],
+    /// xyz: direction towards the sun, w: daylight from 0 at night to 1 during the day
+    sun: [f32; 4],
+    /// x: distance fog starts at, y: distance terrain fully fades into the sky
+    fog: [f32; 4],
+    /// Sky colour at the horizon, which is also the fog colour, in linear RGB
+    sky_horizon: [f32; 4],
+    /// Sky colour straight up, in linear RGB
+    sky_zenith: [f32; 4],
 
 }
 
@@ -20,10 +40,28 @@ impl Globals {
     /// Create global consts from the provided parameters.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        view_proj: [[f32; 4]; 4]
+        view_proj: [[f32; 4]; 4],
+        inv_view_proj: [[f32; 4]; 4],
+        eye_position: [f32; 3],
+        time: f32,
+        time_of_day: f32,
+        sun_direction: [f32; 3],
+        daylight: f32,
+        fog: [f32; 2],
+        sky_horizon: [f32; 3],
+        sky_zenith: [f32; 3],
+        face_colors: bool,
     ) -> Self {
         Self {
-            view_proj
+            view_proj,
+            inv_view_proj,
+            eye_position: [eye_position[0], eye_position[1], eye_position[2], 1.0],
+            debug_flags: [face_colors as u32, 0, 0, 0],
+            time: [time, time_of_day, 0.0, 0.0],
+            sun: [sun_direction[0], sun_direction[1], sun_direction[2], daylight],
+            fog: [fog[0], fog[1], 0.0, 0.0],
+            sky_horizon: [sky_horizon[0], sky_horizon[1], sky_horizon[2], 1.0],
+            sky_zenith: [sky_zenith[0], sky_zenith[1], sky_zenith[2], 1.0],
 
         }
     }
@@ -33,6 +71,16 @@ impl Default for Globals {
     fn default() -> Self {
         Self::new(
             Matrix4::identity().into(),
+            Matrix4::identity().into(),
+            [0.0; 3],
+            0.0,
+            0.25,
+            [0.0, 1.0, 0.0],
+            1.0,
+            [f32::MAX; 2],
+            [0.0; 3],
+            [0.0; 3],
+            false,
 
         )
     }
@@ -48,7 +96,11 @@ pub struct GlobalModel {
 
 pub struct GlobalsLayouts {
     pub globals: wgpu::BindGroupLayout,
-    pub atlas_layout: wgpu::BindGroupLayout
+    pub atlas_layout: wgpu::BindGroupLayout,
+    pub texture_array_layout: wgpu::BindGroupLayout,
+    pub chunk_origins: wgpu::BindGroupLayout,
+    pub shadow_cascade: wgpu::BindGroupLayout,
+    pub post_process: wgpu::BindGroupLayout,
 }
 
 impl GlobalsLayouts {
@@ -65,6 +117,34 @@ impl GlobalsLayouts {
                 },
                 count: None,
             },
+            // Shadow cascades
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Shadow map
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
         ]
     }
 
@@ -82,35 +162,176 @@ impl GlobalsLayouts {
                     ty: wgpu::BindingType::Texture {
                         multisampled: false,
                         view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // Animation of every layer, as in the texture array layout
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Atlas region of every layer
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
                     count: None,
                 },
             ],
             label: Some("atlas_bind_group_layout"),
         });
 
+        let texture_array_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // Animation of every layer
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("texture_array_bind_group_layout"),
+        });
+
+        // Origin of every chunk drawn by the terrain
+        let chunk_origins = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("chunk_origins_bind_group_layout"),
+        });
+
+        // Light view projection of the shadow cascade being drawn
+        let shadow_cascade = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("shadow_cascade_bind_group_layout"),
+        });
+
+        // Frame a post processing stage reads and the post processing settings
+        let post_process = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_process_bind_group_layout"),
+        });
+
         Self {
             globals,
-            atlas_layout
+            atlas_layout,
+            texture_array_layout,
+            chunk_origins,
+            shadow_cascade,
+            post_process,
         }
     }
 
-    fn base_global_entries(
-        global_model: &GlobalModel
-    ) -> Vec<wgpu::BindGroupEntry> {
+    fn base_global_entries<'a>(
+        global_model: &'a GlobalModel,
+        shadow_map: &'a ShadowMap,
+    ) -> Vec<wgpu::BindGroupEntry<'a>> {
         vec![
             // Global uniform
             wgpu::BindGroupEntry {
                 binding: 0,
                 resource: global_model.globals.buf().as_entire_binding(),
             },
+            // Shadow cascades
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: shadow_map.uniform.buf().as_entire_binding(),
+            },
+            // Shadow map
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+            },
         ]
     }
 
@@ -118,11 +339,12 @@ impl GlobalsLayouts {
         &self,
         device: &wgpu::Device,
         global_model: &GlobalModel,
+        shadow_map: &ShadowMap,
     ) -> BindGroup {
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &self.globals,
-            entries: &Self::base_global_entries(global_model),
+            entries: &Self::base_global_entries(global_model, shadow_map),
         });
 
         bind_group
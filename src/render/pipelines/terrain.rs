@@ -3,20 +3,86 @@ use wgpu::RenderPipeline;
 use super::GlobalsLayouts;
 
 
-use crate::render::{texture::Texture, Vertex};
+use crate::render::{shadow::SHADOW_FORMAT, texture::Texture, Vertex};
+use crate::scene::terrain::block::QuadSide;
 
+/// Terrain vertex packed in two words. Positions are local to the chunk, the shader
+/// adds the origin of the chunk from the slot of the chunk in the terrain.
+///
+/// - `position`: x in bits 0-4, y in bits 5-11, z in bits 12-16, the side of the
+///   face in bits 17-19, ambient occlusion in bits 20-21 and light in bits 22-29
+/// - `texture`: texture coordinates in bits 0-4 (u) and 5-9 (v), the texture array
+///   layer in bits 10-21 and the chunk slot in bits 22-31
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BlockVertex {
-    pub pos: [f32; 3],
-    pub texture_coordinates: [f32; 2],
+    position: u32,
+    texture: u32,
 }
 
 impl BlockVertex {
 
     const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
+        wgpu::vertex_attr_array![0 => Uint32, 1 => Uint32];
+
+    /// `texture_coordinates` repeat past 1 so a quad covering several blocks tiles its
+    /// texture. A new vertex is fully lit and unoccluded.
+    pub fn new(pos: [u32; 3], side: QuadSide, texture_coordinates: [u32; 2], layer: u32) -> Self {
+        debug_assert!(pos[0] < 32 && pos[1] < 128 && pos[2] < 32, "vertex out of the chunk: {:?}", pos);
+        debug_assert!(texture_coordinates[0] < 32 && texture_coordinates[1] < 32 && layer < 4096);
+        Self {
+            position: pos[0] | pos[1] << 5 | pos[2] << 12 | (side as u32) << 17,
+            texture: texture_coordinates[0] | texture_coordinates[1] << 5 | layer << 10,
+        }.with_ao(3)
+    }
+
+    /// Position in the chunk.
+    pub fn pos(&self) -> [u32; 3] {
+        [self.position & 31, self.position >> 5 & 127, self.position >> 12 & 31]
+    }
+
+    pub fn texture_coordinates(&self) -> [u32; 2] {
+        [self.texture & 31, self.texture >> 5 & 31]
+    }
+
+    /// Layer of the block texture array.
+    pub fn layer(&self) -> u32 {
+        self.texture >> 10 & 4095
+    }
+
+    /// Slot of the chunk in the terrain, the shader looks its origin up with it.
+    pub fn chunk(&self) -> u32 {
+        self.texture >> 22
+    }
+
+    /// Ambient occlusion of the vertex, from 0 in a fully enclosed corner to 3 in the open.
+    pub fn ao(&self) -> u32 {
+        self.position >> 20 & 3
+    }
+
+    /// Light levels, 0 to 15, of the space the face looks into. Block light in bits
+    /// 0-3 and sky light in bits 4-7.
+    pub fn light(&self) -> u32 {
+        self.position >> 22 & 255
+    }
+
+    pub fn with_ao(self, ao: u32) -> Self {
+        Self { position: self.position & !(3 << 20) | (ao & 3) << 20, ..self }
+    }
+
+    pub fn with_light(self, light: u32) -> Self {
+        Self { position: self.position & !(255 << 22) | (light & 255) << 22, ..self }
+    }
 
+    pub fn with_layer(self, layer: u32) -> Self {
+        debug_assert!(layer < 4096);
+        Self { texture: self.texture & !(4095 << 10) | layer << 10, ..self }
+    }
+
+    pub fn with_chunk(self, chunk: u32) -> Self {
+        debug_assert!(chunk < 1024);
+        Self { texture: self.texture & !(1023 << 22) | chunk << 22, ..self }
+    }
 }
 
 impl Vertex for BlockVertex {
@@ -32,6 +98,30 @@ impl Vertex for BlockVertex {
     }
 }
 
+/// World position of the origin of a chunk, read by the terrain shader at the slot of
+/// the chunk.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChunkOrigin {
+    pub origin: [f32; 4],
+}
+
+/// Terrain geometry is split in layers drawn one after the other, each with its own
+/// pipeline state.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderLayer {
+    /// Fully opaque blocks, depth tested and written without blending.
+    OPAQUE,
+    /// Blocks with holes in their texture (leaves, glass), alpha tested in the shader.
+    CUTOUT,
+    /// Alpha blended blocks (water), drawn last, back to front, without writing depth.
+    TRANSLUCENT,
+}
+
+impl RenderLayer {
+    pub const ALL: [RenderLayer; 3] = [RenderLayer::OPAQUE, RenderLayer::CUTOUT, RenderLayer::TRANSLUCENT];
+}
+
 pub struct TerrainPipeline {
     pub pipeline: RenderPipeline
 }
@@ -40,59 +130,69 @@ impl TerrainPipeline {
     pub fn new(
         device: &wgpu::Device,
         global_layout: &GlobalsLayouts,
-        shader: wgpu::ShaderModule,
-        config: &wgpu::SurfaceConfiguration,//in the future i better add a config struct global
+        // layout of the block textures, the texture array or the atlas one
+        texture_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        layer: RenderLayer,
+        // Anything other than Fill requires Features::POLYGON_MODE_LINE
+        polygon_mode: wgpu::PolygonMode,
+        sample_count: u32,
     ) -> Self {
 
+        let (fragment_entry, blend, depth_write_enabled) = match layer {
+            RenderLayer::OPAQUE => ("fs_main", wgpu::BlendState::REPLACE, true),
+            RenderLayer::CUTOUT => ("fs_cutout", wgpu::BlendState::REPLACE, true),
+            RenderLayer::TRANSLUCENT => ("fs_main", wgpu::BlendState::ALPHA_BLENDING, false),
+        };
+
         let pipeline_layout =
         device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Figure Pipeline Layout"),
             bind_group_layouts: &[
-                &global_layout.atlas_layout,
+                texture_layout,
                 &global_layout.globals,
-                // pendiente agregar layout con algo relacionado del terreno
+                &global_layout.chunk_origins,
             ],
             push_constant_ranges: &[],
         });
 
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render generic Pipeline"),
+            label: Some(&format!("Terrain {:?} Pipeline", layer)),
             layout: Some(&pipeline_layout),
             primitive: wgpu::PrimitiveState { 
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
                 cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Line,
+                polygon_mode,
                 // Requires Features::DEPTH_CLIP_CONTROL
                 unclipped_depth: false,
                 // Requires Features::CONSERVATIVE_RASTERIZATION
                 conservative: false,
             },
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[BlockVertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
+                module: shader,
+                entry_point: fragment_entry,
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    format: Texture::HDR_FORMAT,
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_write_enabled,
+                depth_compare: Texture::DEPTH_COMPARE,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -104,4 +204,103 @@ impl TerrainPipeline {
         }
 
     }
-}
\ No newline at end of file
+}
+
+
+
+/// Depth only terrain pipeline drawing a render layer into a cascade of the shadow map.
+/// Cutout blocks discard their transparent texels, the other layers have no fragment
+/// stage.
+pub struct TerrainShadowPipeline {
+    pub pipeline: RenderPipeline,
+}
+
+impl TerrainShadowPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        global_layout: &GlobalsLayouts,
+        texture_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        layer: RenderLayer,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Shadow Pipeline Layout"),
+            bind_group_layouts: &[
+                texture_layout,
+                &global_layout.shadow_cascade,
+                &global_layout.chunk_origins,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let fragment = match layer {
+            RenderLayer::CUTOUT => Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_cutout",
+                targets: &[],
+            }),
+            _ => None,
+        };
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("Terrain {:?} Shadow Pipeline", layer)),
+            layout: Some(&pipeline_layout),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // faces turned away from the sun cast shadows too, terrain has no
+                // closed volumes to rely on
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[BlockVertex::desc()],
+            },
+            fragment,
+            // the shadow map keeps the usual depth direction, 0 closest to the sun
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_fields_round_trip() {
+        let vertex = BlockVertex::new([16, 100, 7], QuadSide::BACK, [8, 3], 255).with_light(0xa5).with_ao(1).with_chunk(1023);
+        assert_eq!(vertex.pos(), [16, 100, 7]);
+        assert_eq!(vertex.texture_coordinates(), [8, 3]);
+        assert_eq!(vertex.layer(), 255);
+        assert_eq!(vertex.light(), 0xa5);
+        assert_eq!(vertex.ao(), 1);
+        assert_eq!(vertex.chunk(), 1023);
+        assert_eq!(vertex.position >> 17 & 7, QuadSide::BACK as u32);
+        assert_eq!(vertex.with_light(0).with_ao(3).with_chunk(0), BlockVertex::new([16, 100, 7], QuadSide::BACK, [8, 3], 255));
+        assert_eq!(vertex.with_layer(4095).layer(), 4095);
+        assert_eq!(vertex.with_layer(4095).with_layer(3), vertex.with_layer(3));
+        assert_eq!(vertex.with_layer(3).chunk(), 1023);
+    }
+}
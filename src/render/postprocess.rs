@@ -0,0 +1,199 @@
+use crate::{resource_pack::{create_shader_module, ResourcePacks}, settings::PostProcessSettings};
+
+use super::{consts::Consts, pipelines::{postprocess::PostProcessPipeline, GlobalsLayouts}, renderer::catch_validation_errors, texture::Texture};
+
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessUniform {
+    /// x: tonemapping enabled
+    flags: [u32; 4],
+    /// x: brightness, y: gamma
+    params: [f32; 4],
+}
+
+impl From<&PostProcessSettings> for PostProcessUniform {
+    fn from(settings: &PostProcessSettings) -> Self {
+        Self {
+            flags: [settings.tonemapping as u32, 0, 0, 0],
+            params: [settings.brightness, settings.gamma, 0.0, 0.0],
+        }
+    }
+}
+
+
+/// Colour targets of a frame: the HDR one the scene is drawn into, and the display
+/// range one FXAA reads.
+pub struct PostProcessTargets {
+    pub hdr: wgpu::TextureView,
+    ldr: wgpu::TextureView,
+    // inputs of tonemapping and FXAA
+    hdr_bind_group: wgpu::BindGroup,
+    ldr_bind_group: wgpu::BindGroup,
+}
+
+
+/// Turns the HDR frame into the displayed one: tonemapping with brightness and gamma,
+/// then FXAA. FXAA is a pass of its own and is skipped when disabled.
+pub struct PostProcess {
+    settings: PostProcessSettings,
+    uniform: Consts<PostProcessUniform>,
+    sampler: wgpu::Sampler,
+    // format of the frame the last stage draws into
+    format: wgpu::TextureFormat,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    fxaa_pipeline: wgpu::RenderPipeline,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &GlobalsLayouts,
+        config: &wgpu::SurfaceConfiguration,
+        packs: &ResourcePacks,
+    ) -> anyhow::Result<Self> {
+        let settings = PostProcessSettings::default();
+        let mut uniform = Consts::new(device, 1);
+        uniform.update(queue, &[PostProcessUniform::from(&settings)], 0);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_process_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (tonemap_pipeline, fxaa_pipeline) = Self::create_pipelines(device, layouts, config.format, packs)?;
+
+        Ok(Self {
+            settings,
+            uniform,
+            sampler,
+            format: config.format,
+            tonemap_pipeline,
+            fxaa_pipeline,
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layouts: &GlobalsLayouts,
+        format: wgpu::TextureFormat,
+        packs: &ResourcePacks,
+    ) -> anyhow::Result<(wgpu::RenderPipeline, wgpu::RenderPipeline)> {
+        let shader = create_shader_module(device, packs, "postprocess.wgsl", &[])?;
+        Ok((
+            PostProcessPipeline::new(device, layouts, &shader, "fs_tonemap", format).pipeline,
+            PostProcessPipeline::new(device, layouts, &shader, "fs_fxaa", format).pipeline,
+        ))
+    }
+
+    /// Rebuild the pipelines from the current shaders, keeping the old ones if they fail
+    /// to compile.
+    pub fn reload_shaders(
+        &mut self,
+        device: &wgpu::Device,
+        runtime: &tokio::runtime::Handle,
+        layouts: &GlobalsLayouts,
+        packs: &ResourcePacks,
+    ) -> anyhow::Result<()> {
+        (self.tonemap_pipeline, self.fxaa_pipeline) = catch_validation_errors(device, runtime, || Self::create_pipelines(device, layouts, self.format, packs))?;
+        Ok(())
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: &PostProcessSettings) {
+        self.settings = *settings;
+        self.uniform.update(queue, &[settings.into()], 0);
+    }
+
+    /// Targets for frames of the given size.
+    pub fn targets(&self, device: &wgpu::Device, layouts: &GlobalsLayouts, width: u32, height: u32) -> PostProcessTargets {
+        Self::create_targets(device, layouts, &self.uniform, &self.sampler, self.format, width, height)
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layouts: &GlobalsLayouts,
+        uniform: &Consts<PostProcessUniform>,
+        sampler: &wgpu::Sampler,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> PostProcessTargets {
+        let create_target = |label, format| device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor::default());
+        let bind = |view| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post process"),
+            layout: &layouts.post_process,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform.buf().as_entire_binding(),
+                },
+            ],
+        });
+
+        let hdr = create_target("hdr_texture", Texture::HDR_FORMAT);
+        let ldr = create_target("ldr_texture", format);
+        let hdr_bind_group = bind(&hdr);
+        let ldr_bind_group = bind(&ldr);
+        PostProcessTargets { hdr, ldr, hdr_bind_group, ldr_bind_group }
+    }
+
+    /// Post process the HDR frame of `targets` into `output`.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, targets: &PostProcessTargets, output: &wgpu::TextureView) {
+        let tonemap_output = if self.settings.fxaa { &targets.ldr } else { output };
+        Self::encode_stage(encoder, "Tonemap Pass", &self.tonemap_pipeline, &targets.hdr_bind_group, tonemap_output);
+        if self.settings.fxaa {
+            Self::encode_stage(encoder, "FXAA Pass", &self.fxaa_pipeline, &targets.ldr_bind_group, output);
+        }
+    }
+
+    fn encode_stage(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        input: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is drawn
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, input, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::*;
+
+
+// Includes nested deeper than this are assumed to be a mistake
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+
+/// WGSL expanded by the preprocessor, remembering where every line came from so
+/// compile errors can point at the file that was actually written.
+pub struct ShaderSource {
+    pub source: String,
+    // file and 1-based line of every line of `source`
+    origins: Vec<(String, usize)>,
+}
+
+impl ShaderSource {
+    /// File and line a 1-based line of the expanded source comes from.
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        let (file, line) = self.origins.get(line.checked_sub(1)?)?;
+        Some((file.as_str(), *line))
+    }
+}
+
+
+// One `#ifdef` / `#ifndef` block being read
+struct Conditional {
+    active: bool,
+    parent_active: bool,
+    seen_else: bool,
+}
+
+struct State<'a> {
+    load: &'a dyn Fn(&str) -> Result<String>,
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    source: String,
+    origins: Vec<(String, usize)>,
+}
+
+
+/// Expand a shader. Supported directives, on lines of their own:
+///
+/// - `#include "file.wgsl"` pastes another shader, each file is included once
+/// - `#define NAME [value]` and `#undef NAME`, defined names are replaced by their value
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`
+///
+/// `defines` are set before the first line, `load` returns the source of a shader
+/// from its name.
+pub fn preprocess(
+    name: &str,
+    defines: &[(&str, &str)],
+    load: &dyn Fn(&str) -> Result<String>,
+) -> Result<ShaderSource> {
+    let mut state = State {
+        load,
+        defines: defines.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        included: HashSet::new(),
+        source: String::new(),
+        origins: Vec::new(),
+    };
+    process_file(&mut state, name, 0)?;
+
+    Ok(ShaderSource { source: state.source, origins: state.origins })
+}
+
+
+fn process_file(state: &mut State, file: &str, depth: usize) -> Result<()> {
+    ensure!(depth <= MAX_INCLUDE_DEPTH, "includes nested deeper than {MAX_INCLUDE_DEPTH} levels in {file}");
+    if !state.included.insert(file.to_string()) {
+        return Ok(());
+    }
+
+    let text = (state.load)(file)?;
+    let mut conditionals: Vec<Conditional> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let at = || format!("{file}:{line_number}");
+        let active = conditionals.last().map_or(true, |c| c.active);
+        let trimmed = line.trim();
+
+        if let Some(directive) = trimmed.strip_prefix('#') {
+            let mut words = directive.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let argument = words.next();
+            let rest: Vec<&str> = words.collect();
+
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let name = argument.with_context(|| format!("{}: #{keyword} needs a name", at()))?;
+                    let defined = state.defines.contains_key(name);
+                    conditionals.push(Conditional {
+                        active: active && (defined == (keyword == "ifdef")),
+                        parent_active: active,
+                        seen_else: false,
+                    });
+                }
+                "else" => {
+                    let conditional = conditionals.last_mut().with_context(|| format!("{}: #else without #ifdef", at()))?;
+                    ensure!(!conditional.seen_else, "{}: second #else in the same block", at());
+                    conditional.seen_else = true;
+                    conditional.active = conditional.parent_active && !conditional.active;
+                }
+                "endif" => {
+                    conditionals.pop().with_context(|| format!("{}: #endif without #ifdef", at()))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let name = argument.with_context(|| format!("{}: #define needs a name", at()))?;
+                    state.defines.insert(name.to_string(), rest.join(" "));
+                }
+                "undef" => {
+                    let name = argument.with_context(|| format!("{}: #undef needs a name", at()))?;
+                    state.defines.remove(name);
+                }
+                "include" => {
+                    let included = argument
+                        .and_then(|a| a.strip_prefix('"'))
+                        .and_then(|a| a.strip_suffix('"'))
+                        .with_context(|| format!("{}: expected #include \"file.wgsl\"", at()))?;
+                    process_file(state, included, depth + 1)
+                        .with_context(|| format!("included from {}", at()))?;
+                }
+                _ => bail!("{}: unknown directive #{keyword}", at()),
+            }
+            continue;
+        }
+
+        if active {
+            state.source += &substitute(line, &state.defines);
+            state.source.push('\n');
+            state.origins.push((file.to_string(), line_number));
+        }
+    }
+
+    ensure!(conditionals.is_empty(), "{file}: missing #endif");
+    Ok(())
+}
+
+
+// Replace every identifier defined with a value by that value.
+fn substitute(line: &str, defines: &HashMap<String, String>) -> String {
+    if defines.values().all(|value| value.is_empty()) {
+        return line.to_string();
+    }
+
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_alphabetic() || c == '_' {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &line[start..end];
+            match defines.get(word) {
+                Some(value) if !value.is_empty() => out += value,
+                _ => out += word,
+            }
+        } else if c.is_ascii_digit() {
+            // keep literals such as 1u or 0.5f whole
+            out.push(c);
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                    break;
+                }
+                out.push(c);
+                chars.next();
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // preprocess `name` from in-memory files
+    fn expand(files: &[(&str, &str)], name: &str, defines: &[(&str, &str)]) -> Result<ShaderSource> {
+        let files: HashMap<String, String> = files.iter().map(|(n, s)| (n.to_string(), s.to_string())).collect();
+        preprocess(name, defines, &|file| files.get(file).cloned().with_context(|| format!("no shader named {file}")))
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let shader = expand(&[
+            ("main.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain"),
+            ("a.wgsl", "#include \"common.wgsl\"\na"),
+            ("b.wgsl", "#include \"common.wgsl\"\nb"),
+            ("common.wgsl", "common"),
+        ], "main.wgsl", &[]).unwrap();
+        assert_eq!(shader.source, "common\na\nb\nmain\n");
+    }
+
+    #[test]
+    fn nested_conditionals_follow_their_parents() {
+        let source = "\
+#ifdef A
+a
+#ifndef B
+not b
+#else
+b
+#endif
+#else
+not a
+#ifdef B
+b too
+#endif
+#endif
+end";
+        let lines = |defines| expand(&[("main.wgsl", source)], "main.wgsl", defines).unwrap().source;
+        // the #ifndef B block is inside an inactive branch, it stays inactive although B
+        // isn't defined
+        assert_eq!(lines(&[]), "not a\nend\n");
+        assert_eq!(lines(&[("A", "")]), "a\nnot b\nend\n");
+        assert_eq!(lines(&[("A", ""), ("B", "")]), "a\nb\nend\n");
+        assert_eq!(lines(&[("B", "")]), "not a\nb too\nend\n");
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers() {
+        let shader = expand(&[(
+            "main.wgsl",
+            "#define SIZE 16\n#define GROUP 2\n@group(GROUP) var<uniform> a: array<f32, SIZE>;\nlet SIZE_2 = 1u + SIZE + 2SIZE;\n#undef SIZE\nSIZE",
+        )], "main.wgsl", &[("COUNT", "3")]).unwrap();
+        assert_eq!(
+            shader.source,
+            "@group(2) var<uniform> a: array<f32, 16>;\nlet SIZE_2 = 1u + 16 + 2SIZE;\nSIZE\n",
+        );
+
+        // a define inside an inactive block is ignored
+        let shader = expand(&[("main.wgsl", "#ifdef NO\n#define X 1\n#endif\nX")], "main.wgsl", &[]).unwrap();
+        assert_eq!(shader.source, "X\n");
+    }
+
+    #[test]
+    fn unknown_includes_are_errors() {
+        let error = expand(&[("main.wgsl", "a\n#include \"missing.wgsl\"")], "main.wgsl", &[]).err().unwrap();
+        let message = format!("{error:#}");
+        assert!(message.contains("included from main.wgsl:2"), "{message}");
+        assert!(message.contains("no shader named missing.wgsl"), "{message}");
+
+        // unless they are left out
+        assert!(expand(&[("main.wgsl", "#ifdef A\n#include \"missing.wgsl\"\n#endif")], "main.wgsl", &[]).is_ok());
+    }
+
+    #[test]
+    fn unterminated_conditionals_are_errors() {
+        let error = expand(&[
+            ("main.wgsl", "#include \"a.wgsl\"\nmain"),
+            ("a.wgsl", "#ifdef A\na"),
+        ], "main.wgsl", &[]).err().unwrap();
+        assert!(format!("{error:#}").contains("a.wgsl: missing #endif"));
+
+        assert!(expand(&[("main.wgsl", "#endif")], "main.wgsl", &[]).is_err());
+        assert!(expand(&[("main.wgsl", "#ifdef A\n#else\n#else\n#endif")], "main.wgsl", &[]).is_err());
+    }
+
+    #[test]
+    fn lines_map_back_to_their_file() {
+        let shader = expand(&[
+            ("main.wgsl", "// main\n#define A\n#include \"a.wgsl\"\n#ifdef A\nmain 5\n#endif\nmain 7"),
+            ("a.wgsl", "#ifndef A\nskipped\n#endif\na 4"),
+        ], "main.wgsl", &[]).unwrap();
+        assert_eq!(shader.source, "// main\na 4\nmain 5\nmain 7\n");
+        assert_eq!(shader.origin(1), Some(("main.wgsl", 1)));
+        assert_eq!(shader.origin(2), Some(("a.wgsl", 4)));
+        assert_eq!(shader.origin(3), Some(("main.wgsl", 5)));
+        assert_eq!(shader.origin(4), Some(("main.wgsl", 7)));
+        assert_eq!(shader.origin(0), None);
+        assert_eq!(shader.origin(5), None);
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::*;
+
+
+/// Texture contents copied into a buffer the CPU can map, waiting to be read.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    padded_row_bytes: u32,
+}
+
+impl Readback {
+    /// Record the copy of a 2D 8-bit RGBA or BGRA texture, which needs `COPY_SRC` usage.
+    pub fn copy(device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) -> Result<Self> {
+        let format = texture.format();
+        ensure!(
+            matches!(
+                format,
+                wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb
+                    | wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+            ),
+            "can't read back {:?} textures", format
+        );
+        let (width, height) = (texture.width(), texture.height());
+
+        // buffer rows have to be aligned, the padding is dropped once mapped
+        let padded_row_bytes = (4 * width).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: padded_row_bytes as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Ok(Self { buffer, format, width, height, padded_row_bytes })
+    }
+
+    /// Wait for the copy, submitted beforehand, and return the texels as RGBA. sRGB
+    /// textures already hold the encoded values an image file expects, and those of
+    /// linear surfaces are shown as they are, so only BGRA needs its channels swapped.
+    pub fn read(self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()?.context("failed to map the readback buffer")?;
+
+        let row_bytes = 4 * self.width as usize;
+        let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks_exact(self.padded_row_bytes as usize) {
+                pixels.extend_from_slice(&row[..row_bytes]);
+            }
+        }
+        self.buffer.unmap();
+
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for texel in pixels.chunks_exact_mut(4) {
+                texel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels).context("readback size mismatch")
+    }
+}
+
+
+/// Copy a texture to the CPU, blocking until the GPU is done with it.
+pub fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Result<image::RgbaImage> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback encoder"),
+    });
+    let readback = Readback::copy(device, &mut encoder, texture)?;
+    queue.submit(std::iter::once(encoder.finish()));
+    readback.read(device)
+}
@@ -1,16 +1,39 @@
+use anyhow::Context;
 use wgpu::{BindGroup, Error};
 use instant::Instant;
 use winit::window::Window as SysWindow;
 
-use crate::scene::terrain::Terrain;
+use crate::{resource_pack::ResourcePacks, settings::{PostProcessSettings, PresentMode}};
+
+use super::{consts::Consts, graph::{RenderGraph, RenderPhase}, pipelines::{GlobalModel, GlobalsLayouts}, postprocess::{PostProcess, PostProcessTargets}, readback::{read_texture, Readback}, shadow::ShadowMap, texture::Texture};
 
-use super::{consts::Consts, pipelines::{GlobalModel, GlobalsLayouts}, texture::{self, Texture}};
 pub trait Draw {
     fn draw<'a>(
         &'a self, 
         render_pass: &mut wgpu::RenderPass<'a>, 
         globals: &'a wgpu::BindGroup
     ) -> Result<(), Error>;
+
+    /// Draw the depth of whatever casts shadows into one cascade of the shadow map,
+    /// `cascade` holds its light view projection. Nothing casts shadows by default.
+    fn draw_shadow<'a>(
+        &'a self,
+        _render_pass: &mut wgpu::RenderPass<'a>,
+        _cascade: &'a wgpu::BindGroup
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// lets render graphs hold borrowed scene systems
+impl<T: Draw + ?Sized> Draw for &T {
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, globals: &'a wgpu::BindGroup) -> Result<(), Error> {
+        (**self).draw(render_pass, globals)
+    }
+
+    fn draw_shadow<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, cascade: &'a wgpu::BindGroup) -> Result<(), Error> {
+        (**self).draw_shadow(render_pass, cascade)
+    }
 }
 
 
@@ -19,15 +42,46 @@ pub struct Layouts {
 }
 
 
+/// Where frames are drawn.
+enum RenderTarget {
+    Surface(wgpu::Surface),
+    /// Colour texture of a renderer without a window, read back with `Renderer::read_frame`.
+    Offscreen(wgpu::Texture),
+}
+
+
+/// Attachments shared by the phases of the scene pass, for frames of one size.
+struct SceneTargets {
+    depth: Texture,
+    // multisampled colour target resolved into the HDR one, with more than one sample
+    msaa: Option<wgpu::TextureView>,
+    // HDR target the scene is drawn into, and the inputs of post processing
+    post_process: PostProcessTargets,
+}
+
+
 pub struct Renderer {
-    surface: wgpu::Surface,
+    target: RenderTarget,
     pub device: wgpu::Device,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub config: wgpu::SurfaceConfiguration,
     pub queue: wgpu::Queue,
     pub last_render_time: Instant,
     pub layouts: Layouts,
-    depth_texture: Texture
+    /// Runtime of the game, blocks on the futures of the device.
+    pub runtime: tokio::runtime::Handle,
+    // targets of frames drawn at the size of the window
+    targets: SceneTargets,
+    sample_count: u32,
+    // sample counts both the HDR and the depth format support, ascending
+    sample_counts: Vec<u32>,
+    present_modes: Vec<wgpu::PresentMode>,
+    pub shadow_map: ShadowMap,
+    // the scene is drawn into its HDR target, then brought to the frame
+    post_process: PostProcess,
+    // scale of the screenshot to take with the next frame
+    pending_screenshot: Option<u32>,
+    screenshot: Option<anyhow::Result<image::RgbaImage>>,
 }
 
 impl Renderer {
@@ -36,8 +90,7 @@ impl Renderer {
         runtime: &tokio::runtime::Runtime
     ) -> Self {
         let size = window.inner_size();
-        let last_render_time = instant::Instant::now();
-    
+
         // The instance is a handle to our GPU
         // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -59,20 +112,7 @@ impl Renderer {
             },
         )).unwrap();
 
-        let (device, queue) = runtime.block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::POLYGON_MODE_LINE,
-                // WebGL doesn't support all of wgpu's features, so if
-                // we're building for the web, we'll have to disable some.
-                limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                label: None,
-            },
-            None, // Trace path
-        )).unwrap();
+        let (device, queue) = Self::request_device(&adapter, runtime).unwrap();
 
         let surface_caps = surface.get_capabilities(&adapter);
         // Shader code in this tutorial assumes an sRGB surface texture. Using a different
@@ -84,39 +124,204 @@ impl Renderer {
             .next()
             .unwrap_or(surface_caps.formats[0]);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // screenshots copy the frame straight from the surface when it allows it
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            // vsync is the one mode every surface supports
+            present_mode: wgpu::PresentMode::Fifo,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
         surface.configure(&device, &config);
 
+        let sample_counts = Self::adapter_sample_counts(&adapter, &device);
+        Self::with_target(RenderTarget::Surface(surface), device, queue, config, runtime, sample_counts, surface_caps.present_modes)
+    }
+
+    /// Renderer drawing into an offscreen texture, for tests and machines without a
+    /// display. The software fallback adapter is used when `force_fallback_adapter` is
+    /// set or when no other adapter is available.
+    pub fn headless(
+        width: u32,
+        height: u32,
+        runtime: &tokio::runtime::Runtime,
+        force_fallback_adapter: bool,
+    ) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let request_adapter = |force_fallback_adapter| runtime.block_on(instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            },
+        ));
+        let adapter = if force_fallback_adapter { None } else { request_adapter(false) }
+            .or_else(|| request_adapter(true))
+            .context("no graphics adapter available")?;
+
+        let (device, queue) = Self::request_device(&adapter, runtime)?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: Self::OFFSCREEN_FORMAT,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+        let texture = Self::create_offscreen_texture(&device, &config);
+
+        let sample_counts = Self::adapter_sample_counts(&adapter, &device);
+        Ok(Self::with_target(RenderTarget::Offscreen(texture), device, queue, config, runtime, sample_counts, vec![wgpu::PresentMode::Fifo]))
+    }
+
+    pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    fn request_device(
+        adapter: &wgpu::Adapter,
+        runtime: &tokio::runtime::Runtime,
+    ) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+        // Terrain draws through multi_draw_indexed_indirect when available and
+        // falls back to one draw per chunk otherwise. Wireframes fall back to filled
+        // polygons on adapters that can't draw lines, software ones for instance.
+        // Sample counts besides 1 and 4 depend on the adapter's format features.
+        let optional_features = adapter.features()
+            & (wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+        let device = runtime.block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                features: optional_features,
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web, we'll have to disable some.
+                limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+                label: None,
+            },
+            None, // Trace path
+        ))?;
+        Ok(device)
+    }
+
+    // MSAA sample counts the scene's colour and depth targets can use on this device.
+    fn adapter_sample_counts(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Vec<u32> {
+        let adapter_specific = device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let hdr = adapter.get_texture_format_features(Texture::HDR_FORMAT).flags;
+        let depth = adapter.get_texture_format_features(Texture::DEPTH_FORMAT).flags;
+        [1, 2, 4, 8].into_iter()
+            .filter(|&count| count == 1 || adapter_specific || count == 4)
+            .filter(|&count| hdr.sample_count_supported(count) && depth.sample_count_supported(count))
+            .collect()
+    }
+
+    fn with_target(
+        target: RenderTarget,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        runtime: &tokio::runtime::Runtime,
+        sample_counts: Vec<u32>,
+        present_modes: Vec<wgpu::PresentMode>,
+    ) -> Self {
         let layouts = Layouts { global: GlobalsLayouts::new(&device)};
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+        let shadow_map = ShadowMap::new(&device, &layouts.global);
+        // resource packs can replace the shaders with `reload_shaders`
+        let post_process = PostProcess::new(&device, &queue, &layouts.global, &config, &ResourcePacks::embedded()).unwrap();
+        let targets = Self::create_targets(&device, &layouts, &post_process, &config, 1);
 
         Self {
-            surface,
+            target,
             device,
             queue,
+            size: winit::dpi::PhysicalSize::new(config.width, config.height),
             config,
-            size,
-            last_render_time,
+            last_render_time: instant::Instant::now(),
             layouts,
-            depth_texture
+            runtime: runtime.handle().clone(),
+            targets,
+            sample_count: 1,
+            sample_counts,
+            present_modes,
+            shadow_map,
+            post_process,
+            pending_screenshot: None,
+            screenshot: None,
         }
     }
 
+    fn create_targets(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        post_process: &PostProcess,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> SceneTargets {
+        SceneTargets {
+            depth: Texture::create_depth_texture(device, config, sample_count, "depth_texture"),
+            msaa: Self::create_msaa_texture(device, config.width, config.height, sample_count),
+            post_process: post_process.targets(device, &layouts.global, config.width, config.height),
+        }
+    }
+
+    fn create_msaa_texture(device: &wgpu::Device, width: u32, height: u32, sample_count: u32) -> Option<wgpu::TextureView> {
+        if sample_count == 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("msaa_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
+    fn create_offscreen_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &[],
+        })
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-            self.surface.configure(&self.device, &self.config);
+            self.targets = Self::create_targets(&self.device, &self.layouts, &self.post_process, &self.config, self.sample_count);
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen(texture) => *texture = Self::create_offscreen_texture(&self.device, &self.config),
+            }
         }
     }
 
@@ -126,7 +331,72 @@ impl Renderer {
     ) -> BindGroup {
         self.layouts
             .global
-            .bind(&self.device, global_model)
+            .bind(&self.device, global_model, &self.shadow_map)
+    }
+
+    /// MSAA samples per pixel of the scene, pipelines drawing it must match it.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// MSAA sample counts the adapter supports, ascending, 1 is always supported.
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    /// Switch to the largest supported sample count up to `sample_count` and recreate
+    /// the multisampled targets. Pipelines drawing the scene have to be recreated
+    /// afterwards. Returns the sample count in use.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        self.sample_count = self.sample_counts.iter().copied().filter(|&count| count <= sample_count).max().unwrap_or(1);
+        self.targets = Self::create_targets(&self.device, &self.layouts, &self.post_process, &self.config, self.sample_count);
+        self.sample_count
+    }
+
+    /// Present frames with `mode`, or the closest mode the surface supports. Returns the
+    /// mode in use.
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> PresentMode {
+        let fallbacks: &[(PresentMode, wgpu::PresentMode)] = match mode {
+            PresentMode::Immediate => &[(PresentMode::Immediate, wgpu::PresentMode::Immediate), (PresentMode::Mailbox, wgpu::PresentMode::Mailbox)],
+            PresentMode::Mailbox => &[(PresentMode::Mailbox, wgpu::PresentMode::Mailbox)],
+            PresentMode::Vsync => &[],
+        };
+        let (mode, present_mode) = fallbacks.iter().copied()
+            .find(|(_, present_mode)| self.present_modes.contains(present_mode))
+            .unwrap_or((PresentMode::Vsync, wgpu::PresentMode::Fifo));
+
+        self.config.present_mode = present_mode;
+        if let RenderTarget::Surface(surface) = &self.target {
+            surface.configure(&self.device, &self.config);
+        }
+        mode
+    }
+
+    /// Switch post processing stages and settings, from the next frame on.
+    pub fn set_post_process(&mut self, settings: &PostProcessSettings) {
+        self.post_process.set_settings(&self.queue, settings);
+    }
+
+    /// Recompile the shaders owned by the renderer, the previous pipelines are kept if
+    /// they fail to compile.
+    pub fn reload_shaders(&mut self, packs: &ResourcePacks) -> anyhow::Result<()> {
+        self.post_process.reload_shaders(&self.device, &self.runtime, &self.layouts.global, packs)
+    }
+
+    pub fn supports_multi_draw_indirect(&self) -> bool {
+        self.device.features().contains(wgpu::Features::MULTI_DRAW_INDIRECT)
+    }
+
+    pub fn supports_polygon_mode_line(&self) -> bool {
+        self.device.features().contains(wgpu::Features::POLYGON_MODE_LINE)
+    }
+
+    /// Copy the last rendered frame of a headless renderer to the CPU.
+    pub fn read_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        match &self.target {
+            RenderTarget::Offscreen(texture) => read_texture(&self.device, &self.queue, texture),
+            RenderTarget::Surface(_) => anyhow::bail!("only headless renderers can read their frames back"),
+        }
     }
 
     pub fn update(&mut self) {
@@ -147,30 +417,90 @@ impl Renderer {
         consts.update(&self.queue, vals, 0)
     }
 
-    pub fn render(&mut self, terrain: &Terrain, globals: &BindGroup) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    /// Capture the next rendered frame, `scale` times the size of the window. Collect it
+    /// with `take_screenshot` once the frame is rendered.
+    pub fn request_screenshot(&mut self, scale: u32) {
+        let max_scale = self.device.limits().max_texture_dimension_2d / self.config.width.max(self.config.height);
+        self.pending_screenshot = Some(scale.clamp(1, max_scale.max(1)));
+    }
+
+    /// Screenshot captured by the last rendered frame, if one was requested.
+    pub fn take_screenshot(&mut self) -> Option<anyhow::Result<image::RgbaImage>> {
+        self.screenshot.take()
+    }
+
+    /// Draw a frame of `graph`: the shadow map first, then every other phase in order
+    /// in the scene pass, and post processing into the frame.
+    pub fn render(&mut self, graph: &RenderGraph) -> Result<(), wgpu::SurfaceError> {
+        let (output, view) = match &self.target {
+            RenderTarget::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            RenderTarget::Offscreen(texture) => (None, texture.create_view(&wgpu::TextureViewDescriptor::default())),
+        };
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
-        {
-            let mut _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
+        Self::encode_shadows(&mut encoder, &self.shadow_map, graph);
+        self.encode_frame(&mut encoder, graph, &self.targets, &view);
+
+        let screenshot = self.pending_screenshot.take().map(|scale| {
+            let frame = match (&output, &self.target) {
+                (Some(output), _) => &output.texture,
+                (None, RenderTarget::Offscreen(texture)) => texture,
+                (None, RenderTarget::Surface(_)) => unreachable!("surfaces always have an output"),
+            };
+            if scale == 1 && self.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+                Readback::copy(&self.device, &mut encoder, frame)
+            } else {
+                self.render_screenshot(&mut encoder, scale, graph)
+            }
+        });
+
+        
+        // submit will accept anything that implements IntoIter
+        self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(output) = output {
+            output.present();
+        }
+        self.screenshot = screenshot.map(|readback| readback.and_then(|r| r.read(&self.device)));
+    
+        Ok(())
+    }
+
+    // Draw the scene once more in a texture `scale` times larger than the frame. The
+    // projection only depends on the aspect ratio so the picture is the same, and the
+    // shadow map of the frame is reused.
+    fn render_screenshot(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scale: u32,
+        graph: &RenderGraph,
+    ) -> anyhow::Result<Readback> {
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            width: self.config.width * scale,
+            height: self.config.height * scale,
+            ..self.config.clone()
+        };
+        let texture = Self::create_offscreen_texture(&self.device, &config);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let targets = Self::create_targets(&self.device, &self.layouts, &self.post_process, &config, self.sample_count);
+
+        self.encode_frame(encoder, graph, &targets, &view);
+        Readback::copy(&self.device, encoder, &texture)
+    }
+
+    // One depth pass per cascade of the shadow map, from the sun.
+    fn encode_shadows(encoder: &mut wgpu::CommandEncoder, shadow_map: &ShadowMap, graph: &RenderGraph) {
+        for (view, cascade) in shadow_map.cascade_views.iter().zip(&shadow_map.cascade_bind_groups) {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store
@@ -181,17 +511,149 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            terrain.draw(&mut _render_pass, globals).unwrap();
+            for drawable in graph.phase(RenderPhase::SHADOW) {
+                drawable.draw_shadow(&mut render_pass, cascade).unwrap();
+            }
         }
+    }
 
-        
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-    
-        Ok(())
+    // The scene pass into `targets`, then post processing into `output`.
+    fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, graph: &RenderGraph, targets: &SceneTargets, output: &wgpu::TextureView) {
+        Self::encode_scene(encoder, graph, targets);
+        self.post_process.encode(encoder, &targets.post_process, output);
+    }
+
+    // With multisampling the scene is drawn into the MSAA target and resolved into the
+    // HDR one.
+    fn encode_scene(encoder: &mut wgpu::CommandEncoder, graph: &RenderGraph, targets: &SceneTargets) {
+        let view = &targets.post_process.hdr;
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: targets.msaa.as_ref().unwrap_or(view),
+                resolve_target: targets.msaa.as_ref().map(|_| view),
+                ops: wgpu::Operations {
+                    // the sky covers it all
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &targets.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(Texture::DEPTH_CLEAR),
+                    store: wgpu::StoreOp::Store
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        for phase in RenderPhase::SCENE {
+            for drawable in graph.phase(phase) {
+                drawable.draw(&mut render_pass, graph.globals).unwrap();
+            }
+        }
     }
 
 }
 
 
+/// Run `create` with wgpu validation errors captured, returning them instead of letting
+/// them reach the uncaptured error handler, which panics. Wraps the creation of
+/// pipelines from shaders that can change at runtime. `runtime` is the one of the game,
+/// see `Renderer::runtime`.
+pub fn catch_validation_errors<T>(
+    device: &wgpu::Device,
+    runtime: &tokio::runtime::Handle,
+    create: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    // the scope has to be popped even when `create` failed on its own
+    match runtime.block_on(device.pop_error_scope()) {
+        Some(error) => Err(anyhow::anyhow!("{error}")),
+        None => created,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{resource_pack::ResourcePacks, scene::Scene, settings::Settings};
+
+    use super::{catch_validation_errors, Renderer};
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn headless_frame_is_read_back() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut renderer = Renderer::headless(64, 48, &runtime, false).expect("no wgpu adapter");
+
+        let mut scene = Scene::new(&mut renderer, &ResourcePacks::embedded(), &Settings::default());
+        scene.update(&mut renderer, std::time::Duration::from_millis(16));
+        renderer.render(&scene.render_graph()).unwrap();
+
+        let frame = renderer.read_frame().unwrap();
+        assert_eq!(frame.dimensions(), (64, 48));
+        // the camera starts looking down at the terrain, not only at the clear colour
+        let first = *frame.get_pixel(0, 0);
+        assert!(frame.pixels().any(|p| *p != first));
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn multisampled_frame_is_read_back() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut renderer = Renderer::headless(64, 48, &runtime, false).expect("no wgpu adapter");
+
+        // falls back to a supported count, the scene's pipelines are created for it
+        let sample_count = renderer.set_sample_count(8);
+        assert!(renderer.supported_sample_counts().contains(&sample_count));
+        let mut scene = Scene::new(&mut renderer, &ResourcePacks::embedded(), &Settings::default());
+        scene.update(&mut renderer, std::time::Duration::from_millis(16));
+        renderer.render(&scene.render_graph()).unwrap();
+
+        let frame = renderer.read_frame().unwrap();
+        let first = *frame.get_pixel(0, 0);
+        assert!(frame.pixels().any(|p| *p != first));
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn validation_errors_are_returned() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let renderer = Renderer::headless(64, 48, &runtime, false).expect("no wgpu adapter");
+        let device = &renderer.device;
+
+        // the shader reads a uniform the empty pipeline layout doesn't have
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(
+                "@group(0) @binding(0) var<uniform> offset: vec4<f32>;\n\
+                 @vertex fn vs_main() -> @builtin(position) vec4<f32> { return offset; }".into()
+            ),
+        });
+        let created = catch_validation_errors(device, &renderer.runtime, || {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+            Ok(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&layout),
+                vertex: wgpu::VertexState { module: &shader, entry_point: "vs_main", buffers: &[] },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: None,
+                multiview: None,
+            }))
+        });
+        assert!(created.is_err());
+        assert!(catch_validation_errors(device, &renderer.runtime, || Ok(())).is_ok());
+    }
+}
@@ -0,0 +1,210 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3};
+
+use crate::scene::camera::Camera;
+
+use super::{consts::Consts, pipelines::GlobalsLayouts};
+
+
+/// Amount of cascades the view is split in, `shadows.wgsl` has the same constant.
+pub const SHADOW_CASCADES: usize = 3;
+/// Width and height of the shadow map of every cascade.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+/// Weight of the logarithmic split distances against the uniform ones, logarithmic
+/// splits give the cascades close to the camera more resolution.
+const SPLIT_LAMBDA: f32 = 0.75;
+
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+
+/// Cascades as the shaders sample them, part of the globals bind group.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    /// Transformation from world space, relative to the camera's chunk, to the shadow
+    /// map of every cascade
+    cascades: [[[f32; 4]; 4]; SHADOW_CASCADES],
+    /// Distance from the camera every cascade reaches, the last one also ends shadows
+    splits: [f32; 4],
+}
+
+impl Default for ShadowUniform {
+    fn default() -> Self {
+        Self { cascades: [Matrix4::from_scale(0.0).into(); SHADOW_CASCADES], splits: [0.0; 4] }
+    }
+}
+
+
+/// Directional shadow map of the sun, a depth texture layer per cascade. Every cascade
+/// covers a slice of the view frustum as seen from the sun.
+pub struct ShadowMap {
+    pub texture: wgpu::Texture,
+    /// All cascades, for sampling.
+    pub view: wgpu::TextureView,
+    /// One view per cascade to render into.
+    pub cascade_views: Vec<wgpu::TextureView>,
+    pub sampler: wgpu::Sampler,
+    pub uniform: Consts<ShadowUniform>,
+    // light view projection of every cascade pass
+    cascades: Vec<Consts<[[f32; 4]; 4]>>,
+    pub cascade_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, layouts: &GlobalsLayouts) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_map"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: SHADOW_CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..SHADOW_CASCADES as u32).map(|layer| texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_cascade"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })).collect();
+
+        // linear filtering compares the 4 nearest texels, on top of the shader's PCF
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        // zeroed splits leave everything unshadowed until the first update
+        let uniform = Consts::new(device, 1);
+        let cascades: Vec<Consts<[[f32; 4]; 4]>> = (0..SHADOW_CASCADES).map(|_| Consts::new(device, 1)).collect();
+        let cascade_bind_groups = cascades.iter().map(|cascade| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow cascade"),
+            layout: &layouts.shadow_cascade,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: cascade.buf().as_entire_binding(),
+            }],
+        })).collect();
+
+        Self { texture, view, cascade_views, sampler, uniform, cascades, cascade_bind_groups }
+    }
+
+    /// Fit the cascades to the view frustum of `camera` up to `distance` from it, as
+    /// seen from `sun_direction`.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, sun_direction: Vector3<f32>, distance: f32) {
+        let splits = split_distances(camera.projection.znear(), distance);
+        let mut uniform = ShadowUniform::default();
+
+        for i in 0..SHADOW_CASCADES {
+            let near = if i == 0 { camera.projection.znear() } else { splits[i - 1] };
+            let corners = camera.frustum_corners(near, splits[i]);
+            let view_proj: [[f32; 4]; 4] = fit_cascade(&corners, sun_direction, SHADOW_MAP_SIZE).into();
+
+            self.cascades[i].update(queue, &[view_proj], 0);
+            uniform.cascades[i] = view_proj;
+            uniform.splits[i] = splits[i];
+        }
+        self.uniform.update(queue, &[uniform], 0);
+    }
+}
+
+
+// far distance of every cascade, between the uniform and logarithmic split schemes
+fn split_distances(near: f32, far: f32) -> [f32; SHADOW_CASCADES] {
+    let mut splits = [far; SHADOW_CASCADES];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let t = (i + 1) as f32 / SHADOW_CASCADES as f32;
+        let logarithmic = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        *split = SPLIT_LAMBDA * logarithmic + (1.0 - SPLIT_LAMBDA) * uniform;
+    }
+    splits
+}
+
+
+/// Orthographic view projection from the sun covering a slice of the view frustum. The
+/// cascade is fitted to a sphere around the slice so its size doesn't change as the
+/// camera turns, and moves in whole texels so shadow edges don't shimmer.
+pub fn fit_cascade(corners: &[Point3<f32>; 8], sun_direction: Vector3<f32>, map_size: u32) -> Matrix4<f32> {
+    let center = Point3::centroid(corners);
+    let radius = corners.iter().map(|c| (c - center).magnitude()).fold(0.0, f32::max).ceil();
+
+    let up = if sun_direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let light_view = Matrix4::look_to_rh(Point3::origin(), -sun_direction.normalize(), up);
+
+    let texel = 2.0 * radius / map_size as f32;
+    let light_center = light_view * center.to_homogeneous();
+    let (x, y) = ((light_center.x / texel).floor() * texel, (light_center.y / texel).floor() * texel);
+    // anything up to a chunk height towards the sun can cast a shadow into the slice
+    let near = -light_center.z - radius - CASTER_MARGIN;
+    let far = -light_center.z + radius;
+
+    orthographic(x - radius, x + radius, y - radius, y + radius, near, far) * light_view
+}
+
+// extra depth covered towards the sun for casters outside of the view
+const CASTER_MARGIN: f32 = crate::scene::terrain::chunk::CHUNK_Y_SIZE as f32;
+
+
+// Right handed orthographic projection to wgpu's 0..1 depth range, the arguments are
+// columns
+#[rustfmt::skip]
+fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4<f32> {
+    Matrix4::new(
+        2.0 / (right - left), 0.0, 0.0, 0.0,
+        0.0, 2.0 / (top - bottom), 0.0, 0.0,
+        0.0, 0.0, -1.0 / (far - near), 0.0,
+        -(right + left) / (right - left), -(top + bottom) / (top - bottom), -near / (far - near), 1.0,
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Point3, Vector3};
+
+    use super::*;
+
+    #[test]
+    fn cascades_cover_their_frustum_slice() {
+        let corners = [
+            Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, -1.0, -1.0), Point3::new(1.0, 1.0, -1.0), Point3::new(-1.0, 1.0, -1.0),
+            Point3::new(-8.0, -6.0, -20.0), Point3::new(8.0, -6.0, -20.0), Point3::new(8.0, 6.0, -20.0), Point3::new(-8.0, 6.0, -20.0),
+        ];
+        let sun = Vector3::new(0.6, 0.7, 0.2).normalize();
+        let view_proj = fit_cascade(&corners, sun, SHADOW_MAP_SIZE);
+
+        for corner in corners {
+            let p = view_proj * corner.to_homogeneous();
+            assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0, "{:?} outside of the cascade", corner);
+            assert!(p.z > 0.0 && p.z <= 1.0, "{:?} outside of the depth range", corner);
+        }
+        // closer to the sun is shallower
+        let near = view_proj * (Point3::centroid(&corners) + sun * 10.0).to_homogeneous();
+        let far = view_proj * Point3::centroid(&corners).to_homogeneous();
+        assert!(near.z < far.z);
+    }
+
+    #[test]
+    fn splits_grow_up_to_the_distance() {
+        let splits = split_distances(0.1, 64.0);
+        assert!(splits.windows(2).all(|s| s[0] < s[1]));
+        assert!((splits[SHADOW_CASCADES - 1] - 64.0).abs() < 1e-3);
+    }
+}
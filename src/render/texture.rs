@@ -8,9 +8,18 @@ pub struct Texture {
 }
 
 impl Texture {
+    /// Colour target the scene is drawn into, before post processing brings it to the
+    /// display range.
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// Depth is reversed, 1 at the near plane and 0 at infinity, so closer fragments
+    /// have a greater depth. Floats are most precise near 0, which evens out the
+    /// precision lost with distance.
+    pub const DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::Greater;
+    /// Depth of the far plane the depth buffer is cleared to.
+    pub const DEPTH_CLEAR: f32 = 0.0;
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, label: &str) -> Self {
         let size = wgpu::Extent3d {
             width: config.width,
             height: config.height,
@@ -20,11 +29,16 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
+            // multisampled depth is only drawn to, the GL backend can't resolve colour
+            // next to a multisampled depth texture that is also bindable
+            usage: if sample_count == 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            },
             view_formats: &[],
         };
         let tex = device.create_texture(&desc);
@@ -37,7 +51,7 @@ impl Texture {
                 mag_filter: wgpu::FilterMode::Nearest,
                 min_filter: wgpu::FilterMode::Nearest,
                 mipmap_filter: wgpu::FilterMode::Nearest,
-                compare: Some(wgpu::CompareFunction::LessEqual),
+                compare: Some(wgpu::CompareFunction::GreaterEqual),
                 lod_min_clamp: 1000.0,
                 lod_max_clamp: 1000.0,
                 ..Default::default()
@@ -116,7 +130,149 @@ impl Texture {
         Ok(Self { tex:texture, view, sampler })
     }
 
-    
+    /// Create a sampled texture from a full mip chain, `levels[0]` being the base level.
+    /// Minification blends between levels while magnified texels stay sharp, unless
+    /// anisotropic filtering is enabled, which requires linear filtering everywhere.
+    pub fn from_mip_chain(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        levels: &[image::RgbaImage],
+        label: Option<&str>,
+        anisotropy: u16,
+    ) -> Result<Self> {
+        ensure!(!levels.is_empty(), "a texture needs at least one mip level");
+        let (width, height) = levels[0].dimensions();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            }
+        );
+
+        for (level, image) in levels.iter().enumerate() {
+            let (width, height) = image.dimensions();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                image,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+        }
+
+        let anisotropy_clamp = anisotropy.clamp(1, 16);
+        let mag_filter = if anisotropy_clamp > 1 { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                anisotropy_clamp,
+                ..Default::default()
+            }
+        );
+
+        Ok(Self { tex: texture, view, sampler })
+    }
 
-    
-}
\ No newline at end of file
+    /// Create a 2D texture array, `layers[i]` holding the mip chain of layer `i`, base
+    /// level first. Addressing repeats so quads can tile a layer. Minification blends
+    /// between levels while magnified texels stay sharp, unless anisotropic filtering is
+    /// enabled, which requires linear filtering everywhere.
+    pub fn from_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[Vec<image::RgbaImage>],
+        label: Option<&str>,
+        anisotropy: u16,
+    ) -> Result<Self> {
+        ensure!(!layers.is_empty() && !layers[0].is_empty(), "a texture array needs at least one layer");
+        let (width, height) = layers[0][0].dimensions();
+        let mip_level_count = layers[0].len() as u32;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers.len() as u32,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            }
+        );
+
+        for (layer, levels) in layers.iter().enumerate() {
+            ensure!(levels.len() as u32 == mip_level_count, "every layer needs {mip_level_count} mip levels");
+            for (level, image) in levels.iter().enumerate() {
+                let (width, height) = image.dimensions();
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level: level as u32,
+                        origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                    },
+                    image,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * width),
+                        rows_per_image: Some(height),
+                    },
+                    wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                );
+            }
+        }
+
+        let anisotropy_clamp = anisotropy.clamp(1, 16);
+        let mag_filter = if anisotropy_clamp > 1 { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::Repeat,
+                address_mode_w: wgpu::AddressMode::Repeat,
+                mag_filter,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                anisotropy_clamp,
+                ..Default::default()
+            }
+        );
+
+        Ok(Self { tex: texture, view, sampler })
+    }
+}
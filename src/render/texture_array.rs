@@ -0,0 +1,174 @@
+use std::{collections::HashMap, ops::Range, path::Path};
+
+use anyhow::*;
+use image::{imageops::FilterType, RgbaImage};
+
+
+/// Layers of every texture once uploaded, all layers share the same square size.
+/// Animated textures take one consecutive layer per frame.
+#[derive(Clone, Debug, Default)]
+pub struct TextureArrayLayout {
+    pub size: u32,
+    pub mip_levels: u32,
+    pub layer_count: u32,
+    pub layers: HashMap<String, Range<u32>>,
+}
+
+impl TextureArrayLayout {
+    /// Layer of the texture, its first frame when animated.
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.layers.get(name).map(|layers| layers.start)
+    }
+
+    pub fn frame_count(&self, name: &str) -> u32 {
+        self.layers.get(name).map_or(0, |layers| layers.len() as u32)
+    }
+}
+
+
+/// Collects individual textures to be uploaded as the layers of a texture array.
+#[derive(Default)]
+pub struct TextureArrayBuilder {
+    // frames of every texture, a single one for static textures
+    textures: Vec<(String, Vec<RgbaImage>)>,
+}
+
+impl TextureArrayBuilder {
+    pub fn new() -> Self {
+        Self { textures: Vec::new() }
+    }
+
+    /// Add a texture, replacing any previous one with the same name. Both sides have to
+    /// be powers of two, except for animations: vertical strips of square frames.
+    pub fn add(&mut self, name: &str, image: RgbaImage) -> Result<()> {
+        let (width, height) = image.dimensions();
+        let frames = if width.is_power_of_two() && height > width && height % width == 0 {
+            (0..height / width)
+                .map(|i| image::imageops::crop_imm(&image, 0, i * width, width, width).to_image())
+                .collect()
+        } else {
+            ensure!(
+                width.is_power_of_two() && height.is_power_of_two(),
+                "texture {name} is {width}x{height}, sides must be powers of two"
+            );
+            vec![image]
+        };
+
+        self.textures.retain(|(n, _)| n != name);
+        self.textures.push((name.to_string(), frames));
+        Ok(())
+    }
+
+    pub fn add_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        let image = image::load_from_memory(bytes)
+            .with_context(|| format!("failed to decode texture {name}"))?;
+        self.add(name, image.to_rgba8())
+    }
+
+    /// Add every png of a directory, named after their file stem. Returns how many were added.
+    pub fn add_dir(&mut self, dir: &Path) -> Result<usize> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read texture directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "png"))
+            .collect();
+        paths.sort();
+
+        for path in &paths {
+            let name = path.file_stem().and_then(|s| s.to_str())
+                .with_context(|| format!("invalid texture name {}", path.display()))?;
+            let image = image::open(path)
+                .with_context(|| format!("failed to load texture {}", path.display()))?;
+            self.add(name, image.to_rgba8())?;
+        }
+
+        Ok(paths.len())
+    }
+
+    /// Every frame at its own size, in layer order, and the layers of every texture.
+    /// `size` and `mip_levels` of the layout are those `build` gives the array.
+    pub fn frames(self) -> Result<(Vec<RgbaImage>, TextureArrayLayout)> {
+        ensure!(!self.textures.is_empty(), "no textures to put in the texture array");
+
+        let size = self.textures.iter()
+            .flat_map(|(_, frames)| frames.iter())
+            .map(|image| image.width().max(image.height()))
+            .max()
+            .unwrap_or(1);
+        let mip_levels = size.trailing_zeros() + 1;
+
+        let mut layout = TextureArrayLayout { size, mip_levels, layer_count: 0, layers: HashMap::new() };
+        let mut layers = Vec::new();
+        for (name, frames) in self.textures {
+            let first = layers.len() as u32;
+            layers.extend(frames);
+            layout.layers.insert(name, first..layers.len() as u32);
+        }
+        layout.layer_count = layers.len() as u32;
+
+        Ok((layers, layout))
+    }
+
+    /// Scale every texture to the size of the largest one and build their full mip
+    /// chains. Returns the levels of every layer, in layer order, base level first.
+    pub fn build(self) -> Result<(Vec<Vec<RgbaImage>>, TextureArrayLayout)> {
+        let (frames, layout) = self.frames()?;
+        let size = layout.size;
+
+        let layers = frames.into_iter().map(|image| {
+            let base = if image.dimensions() == (size, size) {
+                image
+            } else {
+                image::imageops::resize(&image, size, size, FilterType::Nearest)
+            };
+            let mut levels = vec![base];
+            for _ in 1..layout.mip_levels {
+                let next = downsample(levels.last().unwrap());
+                levels.push(next);
+            }
+            levels
+        }).collect();
+
+        Ok((layers, layout))
+    }
+}
+
+
+// Halve a texture with a box filter. Colours are averaged in linear space and weighted
+// by alpha, so the transparent texels of cutout textures don't darken their edges.
+pub(super) fn downsample(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = ((image.width() / 2).max(1), (image.height() / 2).max(1));
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut color = [0.0f32; 3];
+        let mut alpha = 0.0f32;
+        let mut unweighted = [0.0f32; 3];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let texel = image.get_pixel((2 * x + dx).min(image.width() - 1), (2 * y + dy).min(image.height() - 1));
+            let a = texel[3] as f32 / 255.0;
+            for c in 0..3 {
+                let linear = srgb_to_linear(texel[c]);
+                color[c] += linear * a;
+                unweighted[c] += linear;
+            }
+            alpha += a;
+        }
+
+        let rgb = if alpha > 0.0 { color.map(|c| c / alpha) } else { unweighted.map(|c| c / 4.0) };
+        image::Rgba([
+            linear_to_srgb(rgb[0]),
+            linear_to_srgb(rgb[1]),
+            linear_to_srgb(rgb[2]),
+            (alpha / 4.0 * 255.0).round() as u8,
+        ])
+    })
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+use crate::render::atlas::{BlockDefinitions, TextureAnimations};
+use crate::render::preprocessor::{preprocess, ShaderSource};
+use crate::render::texture_array::TextureArrayBuilder;
+
+
+/// Block textures embedded in the binary, generated by `build.rs` from `assets/textures/block`.
+/// They stand in for the block texture directory when it can't be found.
+const BLOCK_TEXTURES: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/block_textures.rs"));
+
+const BLOCK_DEFINITIONS: &str = include_str!("../assets/blocks.json");
+
+const TEXTURE_ANIMATIONS: &str = include_str!("../assets/animations.json");
+
+const SHADERS: &[(&str, &str)] = &[
+    ("globals.wgsl", include_str!("../assets/shaders/globals.wgsl")),
+    ("texture.wgsl", include_str!("../assets/shaders/texture.wgsl")),
+    ("atmosphere.wgsl", include_str!("../assets/shaders/atmosphere.wgsl")),
+    ("shadows.wgsl", include_str!("../assets/shaders/shadows.wgsl")),
+    ("block_vertex.wgsl", include_str!("../assets/shaders/block_vertex.wgsl")),
+    ("shader.wgsl", include_str!("../assets/shaders/shader.wgsl")),
+    ("shadow.wgsl", include_str!("../assets/shaders/shadow.wgsl")),
+    ("debug.wgsl", include_str!("../assets/shaders/debug.wgsl")),
+    ("sky.wgsl", include_str!("../assets/shaders/sky.wgsl")),
+    ("postprocess.wgsl", include_str!("../assets/shaders/postprocess.wgsl")),
+];
+
+
+/// Ordered list of resource pack directories. Packs mirror the layout of `assets/`
+/// (`textures/block/*.png`, `shaders/*.wgsl`, `blocks.json`, `animations.json`), the
+/// first pack providing a file wins and the assets embedded in the binary fill in for
+/// anything missing.
+#[derive(Clone, Debug, Default)]
+pub struct ResourcePacks {
+    packs: Vec<PathBuf>,
+    // directory of shaders under development, taking precedence over every pack
+    shader_dir: Option<PathBuf>,
+    // directory the base block textures are packed from, under every pack
+    block_texture_dir: Option<PathBuf>,
+}
+
+impl ResourcePacks {
+    pub fn new(packs: Vec<PathBuf>) -> Self {
+        Self { packs, shader_dir: None, block_texture_dir: None }
+    }
+
+    /// Read shaders from `dir` before looking at the packs, to edit them without
+    /// rebuilding.
+    pub fn with_shader_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.shader_dir = dir;
+        self
+    }
+
+    /// Pack the base block textures from the png files of `dir` instead of the ones
+    /// embedded in the binary, which are still used when the directory is missing.
+    pub fn with_block_texture_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.block_texture_dir = dir;
+        self
+    }
+
+    /// Only the embedded assets.
+    pub fn embedded() -> Self {
+        Self::default()
+    }
+
+    pub fn packs(&self) -> &[PathBuf] { &self.packs }
+
+    /// Path of a file in the highest priority pack that has it.
+    pub fn find(&self, relative: &str) -> Option<PathBuf> {
+        self.packs.iter()
+            .map(|pack| pack.join(relative))
+            .find(|path| path.is_file())
+    }
+
+    /// Source of a shader from `shaders/`.
+    pub fn shader(&self, name: &str) -> Result<String> {
+        let dev_path = self.shader_dir.as_ref().map(|dir| dir.join(name)).filter(|path| path.is_file());
+        if let Some(path) = dev_path.or_else(|| self.find(&format!("shaders/{name}"))) {
+            return std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read shader {}", path.display()));
+        }
+
+        SHADERS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, source)| source.to_string())
+            .with_context(|| format!("no shader named {name}"))
+    }
+
+    /// Texture array builder holding every block texture. Packs are added from the
+    /// lowest priority up so their textures replace the ones with the same name.
+    pub fn block_textures(&self) -> Result<TextureArrayBuilder> {
+        let mut builder = TextureArrayBuilder::new();
+        match self.block_texture_dir.as_ref().filter(|dir| dir.is_dir()) {
+            Some(dir) => {
+                builder.add_dir(dir)?;
+            }
+            None => {
+                if let Some(dir) = &self.block_texture_dir {
+                    log::warn!("no block textures in {}, using the embedded ones", dir.display());
+                }
+                for (name, bytes) in BLOCK_TEXTURES {
+                    builder.add_bytes(name, bytes)?;
+                }
+            }
+        }
+
+        for pack in self.packs.iter().rev() {
+            let dir = pack.join("textures/block");
+            if dir.is_dir() {
+                builder.add_dir(&dir)?;
+            }
+        }
+
+        Ok(builder)
+    }
+
+    pub fn block_definitions(&self) -> Result<BlockDefinitions> {
+        match self.find("blocks.json") {
+            Some(path) => {
+                let json = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                parse_block_definitions(&json, &path)
+            }
+            None => parse_block_definitions(BLOCK_DEFINITIONS, Path::new("blocks.json")),
+        }
+    }
+
+    pub fn texture_animations(&self) -> Result<TextureAnimations> {
+        match self.find("animations.json") {
+            Some(path) => {
+                let json = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                parse_texture_animations(&json, &path)
+            }
+            None => parse_texture_animations(TEXTURE_ANIMATIONS, Path::new("animations.json")),
+        }
+    }
+}
+
+
+fn parse_block_definitions(json: &str, path: &Path) -> Result<BlockDefinitions> {
+    serde_json::from_str(json)
+        .with_context(|| format!("invalid block definitions in {}", path.display()))
+}
+
+fn parse_texture_animations(json: &str, path: &Path) -> Result<TextureAnimations> {
+    serde_json::from_str(json)
+        .with_context(|| format!("invalid texture animations in {}", path.display()))
+}
+
+
+/// Create a shader module from a shader of the resource packs, expanded by the
+/// preprocessor with `defines` set. The shader is validated first so a broken one is
+/// reported as an error pointing at the file it comes from instead of a wgpu panic.
+pub fn create_shader_module(
+    device: &wgpu::Device,
+    packs: &ResourcePacks,
+    name: &str,
+    defines: &[(&str, &str)],
+) -> Result<wgpu::ShaderModule> {
+    let shader = preprocess(name, defines, &|file| packs.shader(file))
+        .with_context(|| format!("failed to preprocess shader {name}"))?;
+    validate_shader(&shader, name, shader_capabilities(device.features()))?;
+    Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(name),
+        source: wgpu::ShaderSource::Wgsl(shader.source.into()),
+    }))
+}
+
+// Shader capabilities the device enables, as wgpu checks them when creating the module.
+// Those wgpu derives from downlevel flags of the adapter are left out.
+fn shader_capabilities(features: wgpu::Features) -> naga::valid::Capabilities {
+    use naga::valid::Capabilities;
+    use wgpu::Features;
+
+    let mut capabilities = Capabilities::empty();
+    for (feature, capability) in [
+        (Features::PUSH_CONSTANTS, Capabilities::PUSH_CONSTANT),
+        (Features::SHADER_F64, Capabilities::FLOAT64),
+        (Features::SHADER_PRIMITIVE_INDEX, Capabilities::PRIMITIVE_INDEX),
+        (
+            Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+            Capabilities::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING | Capabilities::SAMPLER_NON_UNIFORM_INDEXING,
+        ),
+        (
+            Features::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+            Capabilities::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+        ),
+        (Features::TEXTURE_FORMAT_16BIT_NORM, Capabilities::STORAGE_TEXTURE_16BIT_NORM_FORMATS),
+        (Features::MULTIVIEW, Capabilities::MULTIVIEW),
+        (Features::SHADER_EARLY_DEPTH_TEST, Capabilities::EARLY_DEPTH_TEST),
+        (Features::DUAL_SOURCE_BLENDING, Capabilities::DUAL_SOURCE_BLENDING),
+    ] {
+        capabilities.set(capability, features.contains(feature));
+    }
+    capabilities
+}
+
+fn validate_shader(shader: &ShaderSource, name: &str, capabilities: naga::valid::Capabilities) -> Result<()> {
+    let source = &shader.source;
+    let origin = |location: Option<naga::SourceLocation>| {
+        location
+            .and_then(|l| shader.origin(l.line_number as usize).map(|(file, line)| (file, line, l.line_position)))
+            .map_or(String::new(), |(file, line, column)| format!(" at {file}:{line}:{column}"))
+    };
+
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| anyhow!(
+        "failed to compile shader {name}{}:\n{}",
+        origin(e.location(source)),
+        e.emit_to_string_with_path(source, name),
+    ))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), capabilities)
+        .validate(&module)
+        .map_err(|e| anyhow!(
+            "invalid shader {name}{}:\n{}",
+            origin(e.location(source)),
+            e.emit_to_string_with_path(source, name),
+        ))?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::atlas::MATERIALS;
+    use crate::render::pipelines::terrain::RenderLayer;
+    use crate::scene::terrain::block::QuadSide;
+
+    #[test]
+    fn translucent_materials_have_translucent_textures() {
+        let definitions = ResourcePacks::embedded().block_definitions().unwrap();
+        for material_type in MATERIALS.iter().filter(|m| m.render_layer() == RenderLayer::TRANSLUCENT) {
+            let name = definitions.texture(*material_type, QuadSide::TOP);
+            let (_, bytes) = BLOCK_TEXTURES.iter().find(|(n, _)| *n == name).unwrap();
+            let image = image::load_from_memory(bytes).unwrap().to_rgba8();
+            assert!(
+                image.pixels().any(|p| p.0[3] < 255),
+                "{name} is opaque but {material_type:?} is blended",
+            );
+        }
+    }
+
+    #[test]
+    fn capabilities_follow_the_device_features() {
+        use naga::valid::Capabilities;
+
+        assert_eq!(shader_capabilities(wgpu::Features::empty()), Capabilities::empty());
+        assert_eq!(
+            shader_capabilities(wgpu::Features::SHADER_F64 | wgpu::Features::MULTI_DRAW_INDIRECT),
+            Capabilities::FLOAT64,
+        );
+
+        // a shader using f64 only validates where the device supports it
+        let shader = preprocess("f64.wgsl", &[], &|_| Ok("fn square(x: f64) -> f64 { return x * x; }".to_string())).unwrap();
+        assert!(validate_shader(&shader, "f64.wgsl", shader_capabilities(wgpu::Features::empty())).is_err());
+        assert!(validate_shader(&shader, "f64.wgsl", shader_capabilities(wgpu::Features::SHADER_F64)).is_ok());
+    }
+
+    #[test]
+    fn block_textures_are_packed_from_their_directory() {
+        let dir = std::env::temp_dir().join(format!("block_textures_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbaImage::new(16, 16).save(dir.join("marble.png")).unwrap();
+
+        let packs = ResourcePacks::embedded().with_block_texture_dir(Some(dir.clone()));
+        let (_, layout) = packs.block_textures().unwrap().frames().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(layout.layers.keys().collect::<Vec<_>>(), ["marble"]);
+
+        // without the directory the embedded textures are used
+        let (_, layout) = packs.block_textures().unwrap().frames().unwrap();
+        assert_eq!(layout.layers.len(), BLOCK_TEXTURES.len());
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+
+// Name of the save file in a world directory
+const WORLD_FILE: &str = "world.json";
+
+
+/// State of a world kept between sessions. Terrain is generated again every time so
+/// only what can't be generated is saved.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WorldSave {
+    /// World time in ticks.
+    #[serde(default)]
+    pub time: u64,
+}
+
+impl WorldSave {
+    /// Read the save of a world directory, `None` for a world that was never saved.
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(WORLD_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let save = serde_json::from_str(&json)
+            .with_context(|| format!("invalid world save {}", path.display()))?;
+        Ok(Some(save))
+    }
+
+    /// Write the save, through a temporary file so a crash can't leave half of it.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(WORLD_FILE);
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write {}", temp.display()))?;
+        std::fs::rename(&temp, &path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
@@ -7,24 +7,20 @@ use std::f32::consts::FRAC_PI_2;
 
 use crate::render::renderer::Renderer;
 
-use super::terrain::{chunk::CHUNK_Y_SIZE, CHUNKS_VIEW_SIZE};
-
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
-);
+use super::terrain::chunk::{chunk_offset_of, CHUNK_AREA};
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 pub struct Dependants {
-    pub view_proj:  [[f32; 4]; 4]
+    pub view_proj:  [[f32; 4]; 4],
+    pub inv_view_proj: [[f32; 4]; 4],
 }
 
+/// Position is kept in double precision, everything is rendered relative to the origin
+/// of the chunk the camera is in so single precision vertices stay precise far from
+/// the world origin.
 pub struct Camera {
-    pub position: Point3<f32>,
+    pub position: Point3<f64>,
     pub yaw: Rad<f32>,
     pub pitch: Rad<f32>,
     pub direction: Vector3<f32>,
@@ -36,13 +32,12 @@ pub struct Camera {
 }
 
 impl Camera {
-    pub fn new<V: Into<Point3<f32>>, Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(renderer: &Renderer, position: V, yaw: Y, pitch: P) -> Self {
+    pub fn new<V: Into<Point3<f64>>, Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(renderer: &Renderer, position: V, yaw: Y, pitch: P) -> Self {
         let projection = Projection::new(
             renderer.config.width,
             renderer.config.height,
             cgmath::Deg(45.0),
             0.1,
-            (CHUNKS_VIEW_SIZE * CHUNK_Y_SIZE as usize) as f32,
         );
         let camera_controller = CameraController::new(10.0, 2.1);
 
@@ -56,7 +51,8 @@ impl Camera {
             camera_controller,
 
             dependants: Dependants {
-                view_proj: Matrix4::identity().into()
+                view_proj: Matrix4::identity().into(),
+                inv_view_proj: Matrix4::identity().into(),
             }
         };
 
@@ -65,12 +61,25 @@ impl Camera {
         return camera;
     }
 
+    /// Offset of the chunk the camera is in, the origin geometry is rendered from.
+    pub fn chunk(&self) -> Vector3<i32> {
+        chunk_offset_of(self.position)
+    }
+
+    /// Position relative to the origin of the camera's chunk.
+    pub fn relative_position(&self) -> Point3<f32> {
+        let chunk = self.chunk();
+        let origin = Vector3::new(chunk.x as f64, 0.0, chunk.z as f64) * CHUNK_AREA as f64;
+        (self.position - origin).cast().unwrap()
+    }
+
+    /// View matrix relative to the origin of the camera's chunk.
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
 
         Matrix4::look_to_rh(
-            self.position,
+            self.relative_position(),
             Vector3::new(
                 cos_pitch * cos_yaw,
                 sin_pitch,
@@ -80,6 +89,30 @@ impl Camera {
         )
     }
 
+    /// Corners of the slice of the view frustum between `near` and `far` from the camera,
+    /// relative to the origin of the camera's chunk. The near corners come first.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Point3<f32>; 8] {
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        let forward = Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+
+        let eye = self.relative_position();
+        let tan_y = (self.projection.fovy / 2.0).tan();
+        let tan_x = tan_y * self.projection.aspect;
+        let mut corners = [eye; 8];
+        for (i, distance) in [near, far].into_iter().enumerate() {
+            let center = eye + forward * distance;
+            let (x, y) = (right * tan_x * distance, up * tan_y * distance);
+            corners[i * 4] = center - x - y;
+            corners[i * 4 + 1] = center + x - y;
+            corners[i * 4 + 2] = center + x + y;
+            corners[i * 4 + 3] = center - x + y;
+        }
+        corners
+    }
+
     pub fn input(&mut self, event: &DeviceEvent) {
         match event {
             DeviceEvent::MouseMotion { delta } => {
@@ -109,8 +142,9 @@ impl Camera {
 
     pub fn update_dependants(&mut self, dt: Duration) {
         self.update_camera_controller(dt);
-        let view_proj:  [[f32; 4]; 4] = (self.projection.calc_matrix() * self.calc_matrix()).into();
-        self.dependants = Dependants {view_proj}
+        let view_proj = self.projection.calc_matrix() * self.calc_matrix();
+        let inv_view_proj = view_proj.invert().unwrap_or(Matrix4::identity());
+        self.dependants = Dependants {view_proj: view_proj.into(), inv_view_proj: inv_view_proj.into()}
     }
 
     pub fn dependants(&self) -> &Dependants { &self.dependants }
@@ -122,8 +156,8 @@ impl Camera {
         let (yaw_sin, yaw_cos) = self.yaw.0.sin_cos();
         let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        self.position += forward * (self.camera_controller.amount_forward - self.camera_controller.amount_backward) * self.camera_controller.speed * dt;
-        self.position += right * (self.camera_controller.amount_right - self.camera_controller.amount_left) * self.camera_controller.speed * dt;
+        let mut movement = forward * (self.camera_controller.amount_forward - self.camera_controller.amount_backward) * self.camera_controller.speed * dt;
+        movement += right * (self.camera_controller.amount_right - self.camera_controller.amount_left) * self.camera_controller.speed * dt;
 
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
@@ -131,12 +165,13 @@ impl Camera {
         // to get closer to an object you want to focus on.
         let (pitch_sin, pitch_cos) = self.pitch.0.sin_cos();
         let scrollward = Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        self.position += scrollward * self.camera_controller.scroll * self.camera_controller.speed * self.camera_controller.sensitivity * dt;
+        movement += scrollward * self.camera_controller.scroll * self.camera_controller.speed * self.camera_controller.sensitivity * dt;
         self.camera_controller.scroll = 0.0;
 
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
-        self.position.y += (self.camera_controller.amount_up - self.camera_controller.amount_down) * self.camera_controller.speed * dt;
+        movement.y += (self.camera_controller.amount_up - self.camera_controller.amount_down) * self.camera_controller.speed * dt;
+        self.position += movement.cast().unwrap();
 
         // Rotate
         self.yaw += Rad(self.camera_controller.rotate_horizontal) * self.camera_controller.sensitivity * dt;
@@ -159,11 +194,12 @@ impl Camera {
 
 
 
+/// Perspective projection without a far plane, mapping depth to wgpu's 0..1 range in
+/// reverse, see `Texture::DEPTH_COMPARE`.
 pub struct Projection {
     aspect: f32,
     fovy: Rad<f32>,
     znear: f32,
-    zfar: f32,
 }
 
 impl Projection {
@@ -172,22 +208,32 @@ impl Projection {
         height: u32,
         fovy: F,
         znear: f32,
-        zfar: f32,
     ) -> Self {
         Self {
             aspect: width as f32 / height as f32,
             fovy: fovy.into(),
             znear,
-            zfar,
         }
     }
 
+    pub fn znear(&self) -> f32 { self.znear }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
 
+    /// Depth is `znear / distance`, 1 at the near plane and approaching 0 at infinity.
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let f = 1.0 / (self.fovy / 2.0).tan();
+        // the arguments are columns
+        #[rustfmt::skip]
+        let projection = Matrix4::new(
+            f / self.aspect, 0.0, 0.0, 0.0,
+            0.0, f, 0.0, 0.0,
+            0.0, 0.0, 0.0, -1.0,
+            0.0, 0.0, self.znear, 0.0,
+        );
+        projection
     }
 }
 
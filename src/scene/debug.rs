@@ -0,0 +1,154 @@
+use cgmath::Vector3;
+use winit::{event::{ElementState, KeyEvent, WindowEvent}, keyboard::{KeyCode, PhysicalKey}};
+
+use crate::resource_pack::{create_shader_module, ResourcePacks};
+use crate::render::{buffer::Buffer, pipelines::debug::{DebugPipeline, DebugVertex}, renderer::{catch_validation_errors, Draw, Renderer}};
+
+use super::terrain::chunk::{CHUNK_AREA, CHUNK_Y_SIZE};
+
+
+/// Height of the sections outlined by `section_borders`.
+pub const SECTION_HEIGHT: usize = 16;
+
+const CHUNK_BORDER_COLOR: [f32; 3] = [1.0, 0.85, 0.0];
+const SECTION_BORDER_COLOR: [f32; 3] = [0.0, 0.6, 1.0];
+
+
+/// Debug visualisations, all of them off until toggled with their hotkey.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DebugSettings {
+    /// F5: draw the terrain as wireframe
+    pub wireframe: bool,
+    /// F6: outline every loaded chunk
+    pub chunk_borders: bool,
+    /// F7: outline the sections chunks are split in
+    pub section_borders: bool,
+    /// F8: tint terrain faces with a colour per direction
+    pub face_colors: bool,
+}
+
+
+pub struct DebugRender {
+    pub settings: DebugSettings,
+    pipeline: wgpu::RenderPipeline,
+    lines: Option<Buffer<DebugVertex>>,
+    // origin and chunks the current lines were built for, None when they have to be rebuilt
+    lines_chunks: Option<(Vector3<i32>, Vec<Vector3<i32>>)>,
+}
+
+impl DebugRender {
+    pub fn new(renderer: &Renderer, packs: &ResourcePacks) -> Self {
+        Self {
+            settings: DebugSettings::default(),
+            pipeline: Self::create_pipeline(renderer, packs).unwrap(),
+            lines: None,
+            lines_chunks: None,
+        }
+    }
+
+    fn create_pipeline(renderer: &Renderer, packs: &ResourcePacks) -> anyhow::Result<wgpu::RenderPipeline> {
+        let shader = create_shader_module(&renderer.device, packs, "debug.wgsl", &[])?;
+
+        Ok(DebugPipeline::new(
+            &renderer.device,
+            &renderer.layouts.global,
+            &shader,
+            renderer.sample_count(),
+        ).pipeline)
+    }
+
+    pub fn reload_resources(&mut self, renderer: &Renderer, packs: &ResourcePacks) -> anyhow::Result<()> {
+        self.pipeline = catch_validation_errors(&renderer.device, &renderer.runtime, || Self::create_pipeline(renderer, packs))?;
+        Ok(())
+    }
+
+    /// Toggle a debug view if the event is one of its hotkeys.
+    pub fn input_keyboard(&mut self, event: &WindowEvent) -> bool {
+        let key = match event {
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key: PhysicalKey::Code(key),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => *key,
+            _ => return false,
+        };
+
+        let toggle = match key {
+            KeyCode::F5 => &mut self.settings.wireframe,
+            KeyCode::F6 => &mut self.settings.chunk_borders,
+            KeyCode::F7 => &mut self.settings.section_borders,
+            KeyCode::F8 => &mut self.settings.face_colors,
+            _ => return false,
+        };
+        *toggle = !*toggle;
+        self.lines_chunks = None;
+        log::info!("debug render: {:?}", self.settings);
+        true
+    }
+
+    /// Rebuild the border lines when a toggle changed or the loaded chunks moved. Lines
+    /// are placed relative to the chunk at `origin`, like the terrain.
+    pub fn update(&mut self, renderer: &Renderer, chunk_offsets: &[Vector3<i32>], origin: Vector3<i32>) {
+        if let Some((lines_origin, lines_chunks)) = &self.lines_chunks {
+            if *lines_origin == origin && lines_chunks == chunk_offsets {
+                return;
+            }
+        }
+        self.lines_chunks = Some((origin, chunk_offsets.to_vec()));
+
+        let mut verts = Vec::new();
+        for offset in chunk_offsets {
+            let x0 = ((offset.x - origin.x) * CHUNK_AREA as i32) as f32;
+            let z0 = ((offset.z - origin.z) * CHUNK_AREA as i32) as f32;
+            let (x1, z1) = (x0 + CHUNK_AREA as f32, z0 + CHUNK_AREA as f32);
+
+            if self.settings.chunk_borders {
+                for (x, z) in [(x0, z0), (x1, z0), (x1, z1), (x0, z1)] {
+                    verts.push(DebugVertex { pos: [x, 0.0, z], color: CHUNK_BORDER_COLOR });
+                    verts.push(DebugVertex { pos: [x, CHUNK_Y_SIZE as f32, z], color: CHUNK_BORDER_COLOR });
+                }
+                push_rect(&mut verts, [x0, z0, x1, z1], 0.0, CHUNK_BORDER_COLOR);
+                push_rect(&mut verts, [x0, z0, x1, z1], CHUNK_Y_SIZE as f32, CHUNK_BORDER_COLOR);
+            }
+
+            if self.settings.section_borders {
+                for y in (SECTION_HEIGHT..CHUNK_Y_SIZE).step_by(SECTION_HEIGHT) {
+                    push_rect(&mut verts, [x0, z0, x1, z1], y as f32, SECTION_BORDER_COLOR);
+                }
+            }
+        }
+
+        self.lines = if verts.is_empty() {
+            None
+        } else {
+            Some(Buffer::new(&renderer.device, wgpu::BufferUsages::VERTEX, &verts))
+        };
+    }
+}
+
+
+fn push_rect(verts: &mut Vec<DebugVertex>, [x0, z0, x1, z1]: [f32; 4], y: f32, color: [f32; 3]) {
+    let corners = [[x0, y, z0], [x1, y, z0], [x1, y, z1], [x0, y, z1]];
+    for i in 0..4 {
+        verts.push(DebugVertex { pos: corners[i], color });
+        verts.push(DebugVertex { pos: corners[(i + 1) % 4], color });
+    }
+}
+
+
+impl Draw for DebugRender {
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, globals: &'a wgpu::BindGroup) -> Result<(), wgpu::Error> {
+        if let Some(lines) = &self.lines {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, globals, &[]);
+            render_pass.set_vertex_buffer(0, lines.buff.slice(..));
+            render_pass.draw(0..lines.len() as u32, 0..1);
+        }
+
+        Ok(())
+    }
+}
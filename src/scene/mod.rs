@@ -2,12 +2,15 @@ use cgmath::point3;
 use wgpu::BindGroup;
 use winit::event::WindowEvent;
 
-use crate::{render::{pipelines::{GlobalModel, Globals}, renderer::Renderer}, GameState};
+use crate::{render::{graph::{RenderGraph, RenderPhase}, hot_reload::join_errors, pipelines::{GlobalModel, Globals}, renderer::Renderer}, resource_pack::ResourcePacks, save::WorldSave, settings::Settings, GameState};
 
-use self::{camera::Camera, terrain::Terrain};
+use self::{camera::Camera, debug::DebugRender, sky::Sky, terrain::Terrain, world_time::WorldTime};
 
 pub mod camera;
+pub mod debug;
+pub mod sky;
 pub mod terrain;
+pub mod world_time;
 
 
 
@@ -16,8 +19,15 @@ pub struct Scene {
     pub data: GlobalModel,
     pub globals_bind_group: BindGroup,
     pub camera: Camera,
+    pub sky: Sky,
     pub terrain: Terrain,
-    pub last_player_pos: cgmath::Point3<f32>
+    pub debug: DebugRender,
+    pub last_player_pos: cgmath::Point3<f32>,
+    /// Seconds of game time, drives texture animations. It wraps around when every
+    /// animation is back at its first frame, so it stays small enough for an f32.
+    pub time: f64,
+    /// Time of the world, drives the day/night cycle.
+    pub world_time: WorldTime,
 }
 
 impl Scene {
@@ -25,7 +35,8 @@ impl Scene {
     pub fn new(
         renderer: &mut Renderer
 ,
-        // settings: &Settings,
+        packs: &ResourcePacks,
+        settings: &Settings,
     ) -> Self {
 
         let data = GlobalModel {
@@ -37,11 +48,18 @@ impl Scene {
 
         let camera = Camera::new(&renderer, (8.0, 12.0, 8.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
 
+        let sky = Sky::new(renderer, packs);
+
         let terrain = Terrain::new(
             &renderer,
+            packs,
+            &settings.graphics,
         );
 
-        
+        let debug = DebugRender::new(renderer, packs);
+
+        let mut world_time = WorldTime::default();
+        world_time.set_scale(settings.time_scale);
 
 
 
@@ -49,13 +67,44 @@ impl Scene {
             data,
             globals_bind_group,
             camera,
+            sky,
             terrain,
-            last_player_pos: point3(0.0, 0.0, 0.0)
+            debug,
+            last_player_pos: point3(0.0, 0.0, 0.0),
+            time: 0.0,
+            world_time,
 
     
         }
     }
 
+    /// Switch to another set of resource packs, reloading every texture and shader.
+    pub fn set_resource_packs(&mut self, renderer: &Renderer, packs: &ResourcePacks, settings: &Settings) -> anyhow::Result<()> {
+        self.terrain.reload_resources(renderer, packs, &settings.graphics)?;
+        self.debug.reload_resources(renderer, packs)?;
+        self.sky.reload_resources(renderer, packs)?;
+        Ok(())
+    }
+
+    /// Recompile every shader, pipelines whose shader fails to compile are kept.
+    pub fn reload_shaders(&mut self, renderer: &Renderer, packs: &ResourcePacks) -> anyhow::Result<()> {
+        let terrain = self.terrain.reload_shaders(renderer, packs);
+        let debug = self.debug.reload_resources(renderer, packs);
+        let sky = self.sky.reload_resources(renderer, packs);
+        join_errors([terrain, debug, sky])
+    }
+
+    /// Continue a saved world.
+    pub fn load(&mut self, save: &WorldSave) {
+        let scale = self.world_time.scale();
+        self.world_time = WorldTime::from_ticks(save.time);
+        self.world_time.set_scale(scale);
+    }
+
+    pub fn save(&self) -> WorldSave {
+        WorldSave { time: self.world_time.ticks() }
+    }
+
     pub fn update 
     (
         &mut self,
@@ -70,26 +119,57 @@ impl Scene {
         //
 
 
-        self.terrain.update(&renderer.queue, &self.camera.position);
-        
-
+        // the camera moves first, the terrain is drawn relative to the chunk the view
+        // is built from
         self.camera.update_dependants(dt);
+        self.terrain.update(renderer, &self.camera.position);
+        self.terrain.set_wireframe(self.debug.settings.wireframe);
+        self.debug.update(renderer, &self.terrain.chunk_offsets(), self.camera.chunk());
+
+        self.time = (self.time + dt.as_secs_f64()) % self.terrain.animation_period();
+        self.world_time.advance(dt);
+        self.sky.update(&self.world_time);
+        // shadows reach as far as the terrain is visible
+        renderer.shadow_map.update(&renderer.queue, &self.camera, self.sky.sun_direction.into(), self.sky.fog_range[1]);
 
         let cam_deps = &self.camera.dependants;
 
         renderer.update_consts(&mut self.data.globals, &[Globals::new(
-            cam_deps.view_proj
+            cam_deps.view_proj,
+            cam_deps.inv_view_proj,
+            self.camera.relative_position().into(),
+            self.time as f32,
+            self.world_time.time_of_day(),
+            self.sky.sun_direction,
+            self.sky.daylight,
+            self.sky.fog_range,
+            self.sky.horizon_color,
+            self.sky.zenith_color,
+            self.debug.settings.face_colors,
 
         )])
 
     }
 
+    /// What the scene draws every frame, in the phase every system belongs to.
+    pub fn render_graph(&self) -> RenderGraph<'_> {
+        let mut graph = RenderGraph::new(&self.globals_bind_group);
+        graph.add(RenderPhase::SHADOW, &self.terrain);
+        graph.add(RenderPhase::BACKGROUND, &self.sky);
+        graph.add(RenderPhase::OPAQUE, &self.terrain);
+        graph.add(RenderPhase::TRANSLUCENT, self.terrain.translucent());
+        graph.add(RenderPhase::OVERLAY, &self.debug);
+        graph
+    }
+
     pub fn handle_input_event(
         &mut self,
         event: &WindowEvent,
         game_state: &GameState
     ) -> bool {
-        if *game_state == GameState::PLAYING{
+        if self.debug.input_keyboard(event) {
+            true
+        } else if *game_state == GameState::PLAYING{
             self.camera.input_keyboard(&event)
         } else {
             false
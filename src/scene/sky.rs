@@ -0,0 +1,95 @@
+use crate::resource_pack::{create_shader_module, ResourcePacks};
+use crate::render::{pipelines::sky::SkyPipeline, renderer::{catch_validation_errors, Draw, Renderer}};
+
+use super::{terrain::RENDER_DISTANCE, world_time::WorldTime};
+
+
+// Fraction of the render distance fog starts at
+const FOG_START: f32 = 0.6;
+
+// Sky colours in linear RGB
+const DAY_HORIZON: [f32; 3] = [0.62, 0.76, 0.92];
+const DAY_ZENITH: [f32; 3] = [0.22, 0.42, 0.82];
+const NIGHT_HORIZON: [f32; 3] = [0.02, 0.025, 0.05];
+const NIGHT_ZENITH: [f32; 3] = [0.004, 0.006, 0.018];
+const SUNSET_HORIZON: [f32; 3] = [0.85, 0.38, 0.16];
+
+
+/// Sky drawn behind the terrain and the fog fading the terrain into it. Fog ends at
+/// the render distance so chunks appear out of the sky rather than popping in.
+/// Colours and sunlight follow the world time, see `update`.
+pub struct Sky {
+    /// Colour at the horizon, which distant terrain fades into, in linear RGB.
+    pub horizon_color: [f32; 3],
+    /// Colour straight up, in linear RGB.
+    pub zenith_color: [f32; 3],
+    /// Direction towards the sun, the moon is opposite.
+    pub sun_direction: [f32; 3],
+    /// Sunlight from 0 at night to 1 during the day.
+    pub daylight: f32,
+    /// Horizontal distances fog starts at and fully hides the terrain at.
+    pub fog_range: [f32; 2],
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Sky {
+    pub fn new(renderer: &Renderer, packs: &ResourcePacks) -> Self {
+        let mut sky = Self {
+            horizon_color: DAY_HORIZON,
+            zenith_color: DAY_ZENITH,
+            sun_direction: [0.0, 1.0, 0.0],
+            daylight: 1.0,
+            fog_range: [RENDER_DISTANCE * FOG_START, RENDER_DISTANCE],
+            pipeline: Self::create_pipeline(renderer, packs).unwrap(),
+        };
+        sky.update(&WorldTime::default());
+        sky
+    }
+
+    /// Move the sun and blend the sky between day and night, with a red horizon while
+    /// the sun is close to it.
+    pub fn update(&mut self, time: &WorldTime) {
+        let sun = time.sun_direction();
+        self.sun_direction = sun.into();
+        self.daylight = time.daylight();
+
+        let twilight = (1.0 - sun.y.abs() * 5.0).max(0.0);
+        for i in 0..3 {
+            let horizon = lerp(NIGHT_HORIZON[i], DAY_HORIZON[i], self.daylight);
+            self.horizon_color[i] = lerp(horizon, SUNSET_HORIZON[i], twilight * 0.7);
+            self.zenith_color[i] = lerp(NIGHT_ZENITH[i], DAY_ZENITH[i], self.daylight);
+        }
+    }
+
+    fn create_pipeline(renderer: &Renderer, packs: &ResourcePacks) -> anyhow::Result<wgpu::RenderPipeline> {
+        let shader = create_shader_module(&renderer.device, packs, "sky.wgsl", &[])?;
+
+        Ok(SkyPipeline::new(
+            &renderer.device,
+            &renderer.layouts.global,
+            &shader,
+            renderer.sample_count(),
+        ).pipeline)
+    }
+
+    pub fn reload_resources(&mut self, renderer: &Renderer, packs: &ResourcePacks) -> anyhow::Result<()> {
+        self.pipeline = catch_validation_errors(&renderer.device, &renderer.runtime, || Self::create_pipeline(renderer, packs))?;
+        Ok(())
+    }
+}
+
+
+impl Draw for Sky {
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, globals: &'a wgpu::BindGroup) -> Result<(), wgpu::Error> {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, globals, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
@@ -4,20 +4,22 @@ use crate::render::atlas::MaterialType;
 
 use crate::render::pipelines::terrain::BlockVertex;
 
-use crate::scene::terrain::chunk::CHUNK_AREA;
 
 
 
-pub fn quad_vertex(pos: [i8; 3], material_type: MaterialType, texture_corners: [u32; 2], position: [i32; 3], quad_side: QuadSide) -> BlockVertex {
-    let tc = material_type.get_texture_coordinates(texture_corners, quad_side);
-    BlockVertex {
-        pos: [
-            pos[0] as f32 + position[0] as f32,
-            pos[1] as f32 + position[1] as f32,
-            pos[2] as f32 + position[2] as f32,
+/// Vertex of a block face. Its texture layer is left at 0, the mesher sets the layer of
+/// the material from the block textures.
+pub fn quad_vertex(pos: [u32; 3], texture_corners: [u32; 2], position: [i32; 3], quad_side: QuadSide) -> BlockVertex {
+    BlockVertex::new(
+        [
+            pos[0] + position[0] as u32,
+            pos[1] + position[1] as u32,
+            pos[2] + position[2] as u32,
         ],
-        texture_coordinates: [tc[0] as f32, tc[1] as f32],
-    }
+        quad_side,
+        texture_corners,
+        0,
+    )
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -42,46 +44,36 @@ impl QuadSide {
         }
     }
 
-    fn get_vertices(self, material_type: MaterialType, position: [i32; 3]) -> [BlockVertex; 4] {
+    /// World axes the texture u and v coordinates of this side run along.
+    fn uv_axes(self) -> [usize; 2] {
+        match self {
+            QuadSide::TOP | QuadSide::BOTTOM => [0, 2],
+            QuadSide::RIGHT | QuadSide::LEFT => [2, 1],
+            QuadSide::FRONT | QuadSide::BACK => [0, 1],
+        }
+    }
+
+    // corners of the face of a unit block with their texture coordinates
+    fn corners(self) -> [([u32; 3], [u32; 2]); 4] {
         match self {
-            QuadSide::TOP => [
-                quad_vertex([0, 1, 0], material_type, [0, 0], position, self),
-                quad_vertex([0, 1, 1], material_type, [0, 1], position, self),
-                quad_vertex([1, 1, 1], material_type, [1, 1], position, self),
-                quad_vertex([1, 1, 0], material_type, [1, 0], position, self),
-            ],
-            QuadSide::BOTTOM => [
-                quad_vertex([0, 0, 1], material_type, [0, 0], position, self),
-                quad_vertex([0, 0, 0], material_type, [0, 1], position, self),
-                quad_vertex([1, 0, 0], material_type, [1, 1], position, self),
-                quad_vertex([1, 0, 1], material_type, [1, 0], position, self),
-            ],
-            QuadSide::RIGHT => [
-                quad_vertex([1, 1, 1], material_type, [0, 0], position, self),
-                quad_vertex([1, 0, 1], material_type, [0, 1], position, self),
-                quad_vertex([1, 0, 0], material_type, [1, 1], position, self),
-                quad_vertex([1, 1, 0], material_type, [1, 0], position, self),
-            ],
-            QuadSide::LEFT => [
-                quad_vertex([0, 1, 0], material_type, [0, 0], position, self),
-                quad_vertex([0, 0, 0], material_type, [0, 1], position, self),
-                quad_vertex([0, 0, 1], material_type, [1, 1], position, self),
-                quad_vertex([0, 1, 1], material_type, [1, 0], position, self),
-            ],
-            QuadSide::FRONT => [
-                quad_vertex([0, 1, 1], material_type, [0, 0], position, self),
-                quad_vertex([0, 0, 1], material_type, [0, 1], position, self),
-                quad_vertex([1, 0, 1], material_type, [1, 1], position, self),
-                quad_vertex([1, 1, 1], material_type, [1, 0], position, self),
-            ],
-            QuadSide::BACK => [
-                quad_vertex([1, 1, 0], material_type, [0, 0], position, self),
-                quad_vertex([1, 0, 0], material_type, [0, 1], position, self),
-                quad_vertex([0, 0, 0], material_type, [1, 1], position, self),
-                quad_vertex([0, 1, 0], material_type, [1, 0], position, self),
-            ],
+            QuadSide::TOP => [([0, 1, 0], [0, 0]), ([0, 1, 1], [0, 1]), ([1, 1, 1], [1, 1]), ([1, 1, 0], [1, 0])],
+            QuadSide::BOTTOM => [([0, 0, 1], [0, 0]), ([0, 0, 0], [0, 1]), ([1, 0, 0], [1, 1]), ([1, 0, 1], [1, 0])],
+            QuadSide::RIGHT => [([1, 1, 1], [0, 0]), ([1, 0, 1], [0, 1]), ([1, 0, 0], [1, 1]), ([1, 1, 0], [1, 0])],
+            QuadSide::LEFT => [([0, 1, 0], [0, 0]), ([0, 0, 0], [0, 1]), ([0, 0, 1], [1, 1]), ([0, 1, 1], [1, 0])],
+            QuadSide::FRONT => [([0, 1, 1], [0, 0]), ([0, 0, 1], [0, 1]), ([1, 0, 1], [1, 1]), ([1, 1, 1], [1, 0])],
+            QuadSide::BACK => [([1, 1, 0], [0, 0]), ([1, 0, 0], [0, 1]), ([0, 0, 0], [1, 1]), ([0, 1, 0], [1, 0])],
         }
     }
+
+    // vertices of the face of a box of `scale` blocks from `position`
+    fn get_vertices(self, position: [i32; 3], scale: [i32; 3]) -> [BlockVertex; 4] {
+        let [u_axis, v_axis] = self.uv_axes();
+        self.corners().map(|(corner, uv)| {
+            let corner = [0, 1, 2].map(|i| corner[i] * scale[i] as u32);
+            let uv = [uv[0] * scale[u_axis] as u32, uv[1] * scale[v_axis] as u32];
+            quad_vertex(corner, uv, position, self)
+        })
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -91,13 +83,21 @@ pub struct Quad {
 }
 
 impl Quad {
-    fn new(material_type: MaterialType, quad_side: QuadSide, position: [i32; 3]) -> Self {
+    fn new(quad_side: QuadSide, position: [i32; 3]) -> Self {
+        Self::new_scaled(quad_side, position, [1, 1, 1])
+    }
+
+    /// Quad of a box spanning `scale` blocks from `position`, used by downsampled LOD
+    /// meshes. The texture repeats once per block.
+    pub fn new_scaled(quad_side: QuadSide, position: [i32; 3], scale: [i32; 3]) -> Self {
         Self {
-            vertices: quad_side.get_vertices(material_type, position),
+            vertices: quad_side.get_vertices(position, scale),
             side: quad_side,
         }
     }
 
+    /// Indices of the two triangles of the `i`th quad of a mesh, split along the
+    /// diagonal from vertex 0 to 2.
     pub fn get_indices(&self, i: u16) -> [u16; 6] {
         let displacement = i * 4;
         [
@@ -109,6 +109,19 @@ impl Quad {
             0 + displacement,
         ]
     }
+
+    /// Same as `get_indices` split along the other diagonal, from vertex 1 to 3.
+    pub fn get_flipped_indices(&self, i: u16) -> [u16; 6] {
+        let displacement = i * 4;
+        [
+            1 + displacement,
+            2 + displacement,
+            3 + displacement,
+            3 + displacement,
+            0 + displacement,
+            1 + displacement,
+        ]
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -121,7 +134,7 @@ pub struct Block {
 
 impl Block {
     pub fn new(material_type: MaterialType, position: [i32; 3], chunk_offset: [i32; 3]) -> Self {
-        let quads = Block::generate_quads(material_type, position, chunk_offset);
+        let quads = Block::generate_quads(position);
 
         Self {
             quads,
@@ -144,19 +157,14 @@ impl Block {
     //     world_pos
     // }
 
-    fn generate_quads(material_type: MaterialType, position: [i32; 3], chunk_offset: [i32; 3]) -> [Quad; 6] {
-        let world_pos = [
-            position[0] + (chunk_offset[0] * CHUNK_AREA as i32),
-            position[1],
-            position[2] + (chunk_offset[2] * CHUNK_AREA as i32),
-        ];
-
-        let top = Quad::new(material_type, QuadSide::TOP, world_pos);
-        let bottom = Quad::new(material_type, QuadSide::BOTTOM, world_pos);
-        let right = Quad::new(material_type, QuadSide::RIGHT, world_pos);
-        let left = Quad::new(material_type, QuadSide::LEFT, world_pos);
-        let front = Quad::new(material_type, QuadSide::FRONT, world_pos);
-        let back = Quad::new(material_type, QuadSide::BACK, world_pos);
+    // quads are built in chunk coordinates, the chunk origin is added when drawing
+    fn generate_quads(position: [i32; 3]) -> [Quad; 6] {
+        let top = Quad::new(QuadSide::TOP, position);
+        let bottom = Quad::new(QuadSide::BOTTOM, position);
+        let right = Quad::new(QuadSide::RIGHT, position);
+        let left = Quad::new(QuadSide::LEFT, position);
+        let front = Quad::new(QuadSide::FRONT, position);
+        let back = Quad::new(QuadSide::BACK, position);
 
         [top, bottom, right, left, front, back]
     }
@@ -164,6 +172,5 @@ impl Block {
     pub fn update(&mut self, new_material_type: MaterialType, offset: [i32; 3]) {
         self.chunk_offset = offset;
         self.material_type = new_material_type;
-        self.quads = Block::generate_quads(new_material_type, self.position, offset);
     }
 }
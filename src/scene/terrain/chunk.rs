@@ -1,14 +1,14 @@
 use std::sync::{Arc, Mutex, RwLock};
 
 
-use cgmath::Vector3;
+use cgmath::{Point3, Vector3};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 
-use crate::render::{atlas::MaterialType, mesh::Mesh, pipelines::terrain::BlockVertex};
+use crate::render::{atlas::MaterialType, mesh::Mesh, pipelines::terrain::{BlockVertex, RenderLayer}};
 
 
-use super::{block::Block, LAND_LEVEL, noise::NoiseGenerator, biomes::BiomeParameters};
+use super::{block::Block, light::LightMap, LAND_LEVEL, noise::NoiseGenerator, biomes::BiomeParameters};
 
 
 pub const CHUNK_Y_SIZE:usize = 100;
@@ -18,13 +18,18 @@ pub const TOTAL_CHUNK_SIZE: usize = CHUNK_Y_SIZE * CHUNK_AREA * CHUNK_AREA;
 
 pub type Blocks = Vec<Vec<Vec<Arc<RwLock<Block>>>>>;
 
+/// One mesh per render layer, indexed by `RenderLayer as usize`.
+pub type LayerMeshes = [Mesh<BlockVertex>; RenderLayer::ALL.len()];
+
 
 #[derive(Default)]
 pub struct Chunk {
     pub blocks: Blocks,
+    pub light: LightMap,
     pub offset: [i32; 3],
     pub updated: bool,
-    pub mesh: Mesh<BlockVertex>,
+    pub meshes: LayerMeshes,
+    pub lod: usize,
     //pub neighbors: [Option<Arc<RwLock<Chunk>>>; 6]
 }
 
@@ -71,7 +76,7 @@ impl Chunk {
                 }
             }
         }
-        Self { updated: true, blocks, offset, /* neighbors: Default::default(),*/ mesh: Default::default()}
+        Self { updated: true, blocks, light: LightMap::default(), offset, /* neighbors: Default::default(),*/ meshes: Default::default(), lod: 0}
     }
 }
 
@@ -86,6 +91,16 @@ pub fn local_pos_to_world(offset: &[i32;3], local_pos: &Vector3<i32>) -> Vector3
 }
 
 
+/// Offset of the chunk holding a world position.
+pub fn chunk_offset_of(world_pos: Point3<f64>) -> Vector3<i32> {
+    Vector3::new(
+        (world_pos.x / CHUNK_AREA as f64).floor() as i32,
+        0,
+        (world_pos.z / CHUNK_AREA as f64).floor() as i32,
+    )
+}
+
+
 pub fn pos_in_chunk_bounds(pos: Vector3<i32>) -> bool {
     if pos.x >= 0 && pos.y >= 0 && pos.z >= 0 {
         if pos.x < CHUNK_AREA as i32 && pos.y < CHUNK_Y_SIZE as i32 && pos.z < CHUNK_AREA as i32 {
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::RwLockWriteGuard;
+
+use cgmath::Vector3;
+
+use crate::render::atlas::MaterialType;
+
+use super::chunk::{Chunk, CHUNK_AREA, CHUNK_Y_SIZE, TOTAL_CHUNK_SIZE};
+
+
+/// Light level of a block next to a light source of the highest emission, and of
+/// blocks open to the sky.
+pub const MAX_LIGHT: u8 = 15;
+
+const NEIGHBORS: [Vector3<i32>; 6] = [
+    Vector3::new(1, 0, 0),
+    Vector3::new(-1, 0, 0),
+    Vector3::new(0, 1, 0),
+    Vector3::new(0, -1, 0),
+    Vector3::new(0, 0, 1),
+    Vector3::new(0, 0, -1),
+];
+
+
+/// The two kinds of light, spread separately.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightChannel {
+    /// Light of emissive blocks, losing a level every block.
+    Block,
+    /// Light coming down from the sky, unchanged as long as it goes straight down
+    /// and losing a level every block once it spreads sideways. How bright it is
+    /// depends on the time of day, which the shader applies.
+    Sky,
+}
+
+impl LightChannel {
+    const ALL: [LightChannel; 2] = [LightChannel::Block, LightChannel::Sky];
+
+    // bit offset of the channel in a packed light value
+    const fn shift(self) -> u8 {
+        match self {
+            LightChannel::Block => 0,
+            LightChannel::Sky => 4,
+        }
+    }
+}
+
+
+/// Pack block and sky light levels the way `LightMap::packed` returns them.
+pub const fn pack_light(block: u8, sky: u8) -> u8 {
+    block | sky << LightChannel::Sky.shift()
+}
+
+
+/// Light levels of every block of a chunk, 0 to `MAX_LIGHT` for each channel.
+#[derive(Clone)]
+pub struct LightMap {
+    // indexed [y][x][z] like the blocks of the chunk, block light in the low nibble
+    // and sky light in the high one
+    levels: Vec<u8>,
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        Self { levels: vec![0; TOTAL_CHUNK_SIZE] }
+    }
+}
+
+impl LightMap {
+    fn index(pos: Vector3<i32>) -> usize {
+        (pos.y as usize * CHUNK_AREA + pos.x as usize) * CHUNK_AREA + pos.z as usize
+    }
+
+    /// Light of a channel at a position inside the chunk.
+    pub fn get(&self, pos: Vector3<i32>, channel: LightChannel) -> u8 {
+        self.levels[Self::index(pos)] >> channel.shift() & 0xF
+    }
+
+    /// Both channels at a position inside the chunk, see `pack_light`.
+    pub fn packed(&self, pos: Vector3<i32>) -> u8 {
+        self.levels[Self::index(pos)]
+    }
+
+    fn set(&mut self, pos: Vector3<i32>, channel: LightChannel, level: u8) {
+        let levels = &mut self.levels[Self::index(pos)];
+        *levels = *levels & !(0xF << channel.shift()) | level << channel.shift();
+    }
+
+    fn clear(&mut self) {
+        self.levels.fill(0);
+    }
+}
+
+
+/// Loaded chunks locked for writing while light spreads through them, addressed with
+/// world block positions so light crosses chunk borders. Chunks whose light changed
+/// are remembered so they can be remeshed.
+pub struct LightVolume<'a> {
+    chunks: HashMap<[i32; 2], RwLockWriteGuard<'a, Chunk>>,
+    changed: HashSet<[i32; 2]>,
+}
+
+impl<'a> LightVolume<'a> {
+    pub fn new(chunks: impl IntoIterator<Item = RwLockWriteGuard<'a, Chunk>>) -> Self {
+        Self {
+            chunks: chunks.into_iter().map(|chunk| ([chunk.offset[0], chunk.offset[2]], chunk)).collect(),
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Offsets of the chunks whose light changed.
+    pub fn changed_chunks(&self) -> Vec<[i32; 2]> {
+        self.changed.iter().copied().collect()
+    }
+
+    /// Light a freshly generated chunk from its light sources, the sky above its
+    /// columns and the light of the loaded chunks around it, which may spread further
+    /// into them.
+    pub fn light_chunk(&mut self, offset: [i32; 2]) {
+        let Some(chunk) = self.chunks.get_mut(&offset) else { return };
+        chunk.light.clear();
+        self.changed.insert(offset);
+
+        let origin = chunk_origin(offset);
+        let mut block_queue = VecDeque::new();
+        let mut sky_queue = VecDeque::new();
+        for x in 0..CHUNK_AREA as i32 {
+            for z in 0..CHUNK_AREA as i32 {
+                // sunlight fills every column down to its first opaque block
+                let mut open_to_sky = true;
+                for y in (0..CHUNK_Y_SIZE as i32).rev() {
+                    let pos = origin + Vector3::new(x, y, z);
+                    let Some(material) = self.material(pos) else { continue };
+                    open_to_sky &= material.transmits_light();
+                    if open_to_sky {
+                        self.set_light(pos, LightChannel::Sky, MAX_LIGHT);
+                        sky_queue.push_back(pos);
+                    }
+
+                    let emission = material.light_emission();
+                    if emission > 0 {
+                        self.set_light(pos, LightChannel::Block, emission);
+                        block_queue.push_back(pos);
+                    }
+                }
+            }
+        }
+
+        // light of the neighbors flows back in through their side facing this chunk
+        for pos in border_blocks(offset) {
+            if self.light(pos, LightChannel::Block).map_or(false, |level| level > 1) {
+                block_queue.push_back(pos);
+            }
+            if self.light(pos, LightChannel::Sky).map_or(false, |level| level > 1) {
+                sky_queue.push_back(pos);
+            }
+        }
+
+        self.propagate(LightChannel::Block, block_queue);
+        self.propagate(LightChannel::Sky, sky_queue);
+    }
+
+    /// Drop the light that came from a chunk which is no longer loaded, it lingers on
+    /// the sides of its neighbors otherwise. The unloaded chunk isn't part of the
+    /// volume, its light is removed as if the blocks along its sides went dark so the
+    /// neighbors keep the light of their own sources and of the sky.
+    pub fn unload_chunk(&mut self, chunk: &Chunk) {
+        let offset = [chunk.offset[0], chunk.offset[2]];
+        let origin = chunk_origin(offset);
+        for channel in LightChannel::ALL {
+            let seeds = side_blocks(offset)
+                .map(|pos| (pos, chunk.light.get(pos - origin, channel)))
+                .filter(|&(_, level)| level > 0)
+                .collect();
+            self.remove(channel, seeds);
+        }
+    }
+
+    /// Relight around a block that changed material, removing the light it emitted or
+    /// now blocks and letting the light around it in if it became see-through.
+    pub fn block_changed(&mut self, pos: Vector3<i32>, new: MaterialType) {
+        for channel in LightChannel::ALL {
+            if let Some(level) = self.light(pos, channel).filter(|&level| level > 0) {
+                self.set_light(pos, channel, 0);
+                self.remove(channel, vec![(pos, level)]);
+            }
+        }
+
+        let emission = new.light_emission();
+        if emission > 0 {
+            self.set_light(pos, LightChannel::Block, emission);
+            self.propagate(LightChannel::Block, VecDeque::from([pos]));
+        }
+
+        if new.transmits_light() {
+            for channel in LightChannel::ALL {
+                let mut lit: VecDeque<_> = NEIGHBORS.iter()
+                    .map(|n| pos + n)
+                    .filter(|&n| self.light(n, channel).map_or(false, |level| level > 1))
+                    .collect();
+                // nothing above the top of the world holds the sunlight
+                if channel == LightChannel::Sky && pos.y == CHUNK_Y_SIZE as i32 - 1 {
+                    self.set_light(pos, channel, MAX_LIGHT);
+                    lit.push_back(pos);
+                }
+                self.propagate(channel, lit);
+            }
+        }
+    }
+
+    // Flood fill from lit blocks, every step through a see-through block loses a
+    // level, except for full sunlight going down.
+    fn propagate(&mut self, channel: LightChannel, mut queue: VecDeque<Vector3<i32>>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = self.light(pos, channel).unwrap_or(0);
+            if level <= 1 {
+                continue;
+            }
+            for step in NEIGHBORS {
+                let neighbor = pos + step;
+                let (Some(neighbor_level), Some(material)) = (self.light(neighbor, channel), self.material(neighbor)) else { continue };
+                let new_level = if unattenuated(channel, step, level) { level } else { level - 1 };
+                if material.transmits_light() && neighbor_level < new_level {
+                    self.set_light(neighbor, channel, new_level);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    // Darken the blocks lit by the removed levels, already set to 0 or unloaded, then fill the hole
+    // again from the brighter blocks found around it, which have another source.
+    fn remove(&mut self, channel: LightChannel, seeds: Vec<(Vector3<i32>, u8)>) {
+        let mut queue = VecDeque::from(seeds);
+        let mut relight = VecDeque::new();
+        while let Some((pos, level)) = queue.pop_front() {
+            for step in NEIGHBORS {
+                let neighbor = pos + step;
+                let Some(neighbor_level) = self.light(neighbor, channel).filter(|&level| level > 0) else { continue };
+                if neighbor_level < level || unattenuated(channel, step, level) && neighbor_level == level {
+                    self.set_light(neighbor, channel, 0);
+                    queue.push_back((neighbor, neighbor_level));
+                    let emission = self.material(neighbor).map_or(0, |m| m.light_emission());
+                    if channel == LightChannel::Block && emission > 0 {
+                        self.set_light(neighbor, channel, emission);
+                        relight.push_back(neighbor);
+                    }
+                } else {
+                    relight.push_back(neighbor);
+                }
+            }
+        }
+        self.propagate(channel, relight);
+    }
+
+    // chunk and position inside it of a world block position, if it's loaded
+    fn locate(&self, pos: Vector3<i32>) -> Option<([i32; 2], Vector3<i32>)> {
+        if pos.y < 0 || pos.y >= CHUNK_Y_SIZE as i32 {
+            return None;
+        }
+        let size = CHUNK_AREA as i32;
+        let offset = [pos.x.div_euclid(size), pos.z.div_euclid(size)];
+        self.chunks.contains_key(&offset)
+            .then(|| (offset, Vector3::new(pos.x.rem_euclid(size), pos.y, pos.z.rem_euclid(size))))
+    }
+
+    fn light(&self, pos: Vector3<i32>, channel: LightChannel) -> Option<u8> {
+        let (offset, local) = self.locate(pos)?;
+        Some(self.chunks[&offset].light.get(local, channel))
+    }
+
+    fn set_light(&mut self, pos: Vector3<i32>, channel: LightChannel, level: u8) {
+        if let Some((offset, local)) = self.locate(pos) {
+            self.chunks.get_mut(&offset).unwrap().light.set(local, channel, level);
+            self.changed.insert(offset);
+        }
+    }
+
+    fn material(&self, pos: Vector3<i32>) -> Option<MaterialType> {
+        let (offset, local) = self.locate(pos)?;
+        let block = self.chunks[&offset].blocks[local.y as usize][local.x as usize][local.z as usize].read().unwrap();
+        Some(block.material_type)
+    }
+}
+
+
+// full sunlight keeps its level going down
+fn unattenuated(channel: LightChannel, step: Vector3<i32>, level: u8) -> bool {
+    channel == LightChannel::Sky && level == MAX_LIGHT && step.y == -1
+}
+
+fn chunk_origin(offset: [i32; 2]) -> Vector3<i32> {
+    Vector3::new(offset[0] * CHUNK_AREA as i32, 0, offset[1] * CHUNK_AREA as i32)
+}
+
+// world positions of the blocks of the neighboring chunks touching the sides of a chunk
+fn border_blocks(offset: [i32; 2]) -> impl Iterator<Item = Vector3<i32>> {
+    let origin = chunk_origin(offset);
+    let size = CHUNK_AREA as i32;
+    (0..CHUNK_Y_SIZE as i32).flat_map(move |y| {
+        (0..size).flat_map(move |i| [
+            origin + Vector3::new(-1, y, i),
+            origin + Vector3::new(size, y, i),
+            origin + Vector3::new(i, y, -1),
+            origin + Vector3::new(i, y, size),
+        ])
+    })
+}
+
+
+// world positions of the blocks along the sides of a chunk
+fn side_blocks(offset: [i32; 2]) -> impl Iterator<Item = Vector3<i32>> {
+    let origin = chunk_origin(offset);
+    let size = CHUNK_AREA as i32;
+    (0..CHUNK_Y_SIZE as i32).flat_map(move |y| {
+        (0..size).flat_map(move |i| [
+            origin + Vector3::new(0, y, i),
+            origin + Vector3::new(size - 1, y, i),
+            origin + Vector3::new(i, y, 0),
+            origin + Vector3::new(i, y, size - 1),
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use super::*;
+
+    fn set_block(chunks: &[RwLock<Chunk>], pos: Vector3<i32>, material_type: MaterialType) {
+        let chunk = chunks[pos.x.div_euclid(CHUNK_AREA as i32) as usize].read().unwrap();
+        let local = Vector3::new(pos.x.rem_euclid(CHUNK_AREA as i32), pos.y, pos.z);
+        chunk.blocks[local.y as usize][local.x as usize][local.z as usize].write().unwrap().update(material_type, chunk.offset);
+    }
+
+    fn light(chunks: &[RwLock<Chunk>], pos: Vector3<i32>) -> u8 {
+        let volume = LightVolume::new(chunks.iter().map(|c| c.write().unwrap()));
+        volume.light(pos, LightChannel::Block).unwrap()
+    }
+
+    fn sky(chunks: &[RwLock<Chunk>], pos: Vector3<i32>) -> u8 {
+        let volume = LightVolume::new(chunks.iter().map(|c| c.write().unwrap()));
+        volume.light(pos, LightChannel::Sky).unwrap()
+    }
+
+    fn change(chunks: &[RwLock<Chunk>], pos: Vector3<i32>, material_type: MaterialType) {
+        set_block(chunks, pos, material_type);
+        LightVolume::new(chunks.iter().map(|c| c.write().unwrap())).block_changed(pos, material_type);
+    }
+
+    // two chunks side by side along x, solid below y = 10 and empty above
+    fn chunks() -> Vec<RwLock<Chunk>> {
+        vec![RwLock::new(Chunk::new([0, 0, 0])), RwLock::new(Chunk::new([1, 0, 0]))]
+    }
+
+    #[test]
+    fn light_crosses_chunk_borders() {
+        let chunks = chunks();
+        let lava = Vector3::new(15, 20, 5);
+        change(&chunks, lava, MaterialType::LAVA);
+
+        assert_eq!(light(&chunks, lava), 15);
+        assert_eq!(light(&chunks, Vector3::new(16, 20, 5)), 14);
+        assert_eq!(light(&chunks, Vector3::new(20, 21, 5)), 9);
+        // solid blocks stay dark
+        assert_eq!(light(&chunks, Vector3::new(15, 9, 5)), 0);
+    }
+
+    #[test]
+    fn removed_light_is_restored_from_other_sources() {
+        let chunks = chunks();
+        let (a, b) = (Vector3::new(15, 20, 5), Vector3::new(19, 20, 5));
+        change(&chunks, a, MaterialType::LAVA);
+        change(&chunks, b, MaterialType::LAVA);
+        change(&chunks, a, MaterialType::AIR);
+
+        assert_eq!(light(&chunks, a), 11);
+        assert_eq!(light(&chunks, Vector3::new(12, 20, 5)), 8);
+
+        change(&chunks, b, MaterialType::AIR);
+        for chunk in &chunks {
+            assert!(chunk.read().unwrap().light.levels.iter().all(|&level| level & 0xF == 0));
+        }
+    }
+
+    #[test]
+    fn placed_blocks_cast_shadows() {
+        let chunks = chunks();
+        let lava = Vector3::new(8, 10, 8);
+        change(&chunks, lava, MaterialType::LAVA);
+        assert_eq!(light(&chunks, Vector3::new(8, 12, 8)), 13);
+
+        change(&chunks, Vector3::new(8, 11, 8), MaterialType::ROCK);
+        // around the rock instead of through it
+        assert_eq!(light(&chunks, Vector3::new(8, 11, 8)), 0);
+        assert_eq!(light(&chunks, Vector3::new(8, 12, 8)), 11);
+    }
+
+    #[test]
+    fn sunlight_fills_holes_and_spreads_under_overhangs() {
+        let chunks = chunks();
+        {
+            let mut volume = LightVolume::new(chunks.iter().map(|c| c.write().unwrap()));
+            volume.light_chunk([0, 0]);
+            volume.light_chunk([1, 0]);
+        }
+        assert_eq!(sky(&chunks, Vector3::new(20, 10, 3)), MAX_LIGHT);
+        assert_eq!(sky(&chunks, Vector3::new(20, 9, 3)), 0);
+
+        // a hole in the ground is lit all the way down
+        for y in 5..10 {
+            change(&chunks, Vector3::new(15, y, 3), MaterialType::AIR);
+        }
+        assert_eq!(sky(&chunks, Vector3::new(15, 5, 3)), MAX_LIGHT);
+
+        // covering it turns the full sunlight into light spreading in from the side
+        change(&chunks, Vector3::new(15, 10, 3), MaterialType::ROCK);
+        change(&chunks, Vector3::new(16, 10, 3), MaterialType::ROCK);
+        assert_eq!(sky(&chunks, Vector3::new(15, 9, 3)), 0);
+        change(&chunks, Vector3::new(15, 10, 3), MaterialType::AIR);
+        assert_eq!(sky(&chunks, Vector3::new(15, 5, 3)), MAX_LIGHT);
+        change(&chunks, Vector3::new(15, 10, 3), MaterialType::ROCK);
+        change(&chunks, Vector3::new(14, 9, 3), MaterialType::AIR);
+        assert_eq!(sky(&chunks, Vector3::new(15, 9, 3)), MAX_LIGHT - 1);
+        assert_eq!(sky(&chunks, Vector3::new(15, 5, 3)), MAX_LIGHT - 5);
+    }
+
+    #[test]
+    fn unloading_keeps_the_light_of_border_emitters() {
+        let chunks = chunks();
+        change(&chunks, Vector3::new(14, 20, 5), MaterialType::LAVA);
+        change(&chunks, Vector3::new(16, 20, 9), MaterialType::LAVA);
+        assert_eq!(light(&chunks, Vector3::new(16, 20, 5)), 13);
+
+        LightVolume::new([chunks[1].write().unwrap()]).unload_chunk(&chunks[0].read().unwrap());
+        let volume = LightVolume::new([chunks[1].write().unwrap()]);
+        assert_eq!(volume.light(Vector3::new(16, 20, 9), LightChannel::Block), Some(15));
+        assert_eq!(volume.light(Vector3::new(16, 20, 8), LightChannel::Block), Some(14));
+        // only lit by the border lava now
+        assert_eq!(volume.light(Vector3::new(16, 20, 5), LightChannel::Block), Some(11));
+        assert_eq!(volume.light(Vector3::new(16, 20, 0), LightChannel::Block), Some(6));
+    }
+
+    #[test]
+    fn unloading_keeps_the_sunlight_of_open_border_columns() {
+        let chunks = chunks();
+        // a shaft down to a pocket under the neighbor, lit from the side
+        for y in 5..10 {
+            set_block(&chunks, Vector3::new(15, y, 3), MaterialType::AIR);
+        }
+        set_block(&chunks, Vector3::new(16, 5, 3), MaterialType::AIR);
+        {
+            let mut volume = LightVolume::new(chunks.iter().map(|c| c.write().unwrap()));
+            volume.light_chunk([0, 0]);
+            volume.light_chunk([1, 0]);
+        }
+        assert_eq!(sky(&chunks, Vector3::new(16, 5, 3)), MAX_LIGHT - 1);
+
+        LightVolume::new([chunks[1].write().unwrap()]).unload_chunk(&chunks[0].read().unwrap());
+        let volume = LightVolume::new([chunks[1].write().unwrap()]);
+        for y in 10..CHUNK_Y_SIZE as i32 {
+            assert_eq!(volume.light(Vector3::new(16, y, 3), LightChannel::Sky), Some(MAX_LIGHT));
+        }
+        assert_eq!(volume.light(Vector3::new(16, 5, 3), LightChannel::Sky), Some(0));
+    }
+}
@@ -0,0 +1,147 @@
+use cgmath::Vector3;
+
+use crate::render::atlas::MaterialType;
+
+use super::{chunk::{Blocks, CHUNK_AREA, CHUNK_Y_SIZE}, CHUNKS_VIEW_SIZE};
+
+
+/// Voxel downsampling factor of every LOD level, level 0 being full resolution.
+pub const LOD_FACTORS: [usize; 4] = [1, 2, 4, 8];
+
+/// Chunk distance from the player's chunk at which each coarser LOD level starts
+/// (level 1 starts at `LOD_DISTANCES[0]`, level 2 at `LOD_DISTANCES[1]`, ...).
+pub const LOD_DISTANCES: [i32; 3] = lod_distances(CHUNKS_VIEW_SIZE as i32 / 2);
+
+// The player's chunk and the ones around it are always meshed at full resolution
+const FULL_RESOLUTION_DISTANCE: i32 = 1;
+
+/// LOD distances for a view reaching `view_radius` chunks from the player's chunk. The
+/// coarser levels split the rings past the full resolution ones evenly, at least one
+/// ring each, so the outermost loaded ring always gets a coarser level.
+pub const fn lod_distances(view_radius: i32) -> [i32; 3] {
+    let first = FULL_RESOLUTION_DISTANCE + 1;
+    let rings = view_radius - FULL_RESOLUTION_DISTANCE;
+    let width = if rings > 3 { rings / 3 } else { 1 };
+    [first, first + width, first + 2 * width]
+}
+
+
+/// Chebyshev distance between two chunk offsets, ignoring the y axis.
+pub fn chunk_distance(a: Vector3<i32>, b: Vector3<i32>) -> i32 {
+    (a.x - b.x).abs().max((a.z - b.z).abs())
+}
+
+pub fn lod_for_distance(distance: i32) -> usize {
+    LOD_DISTANCES.iter().take_while(|&&d| distance >= d).count()
+}
+
+/// Amount of downsampled cells along each axis of a chunk at the given LOD.
+pub fn cells_per_chunk(lod: usize) -> Vector3<i32> {
+    let factor = LOD_FACTORS[lod];
+    Vector3::new(
+        (CHUNK_AREA / factor) as i32,
+        ((CHUNK_Y_SIZE + factor - 1) / factor) as i32,
+        (CHUNK_AREA / factor) as i32,
+    )
+}
+
+pub fn cell_in_bounds(cell: Vector3<i32>, lod: usize) -> bool {
+    let cells = cells_per_chunk(lod);
+    cell.x >= 0 && cell.y >= 0 && cell.z >= 0 && cell.x < cells.x && cell.y < cells.y && cell.z < cells.z
+}
+
+/// Size in blocks of a cell, the top row of cells is clamped to the chunk height.
+pub fn cell_size(cell: Vector3<i32>, lod: usize) -> [i32; 3] {
+    let factor = LOD_FACTORS[lod] as i32;
+    let height = factor.min(CHUNK_Y_SIZE as i32 - cell.y * factor);
+    [factor, height, factor]
+}
+
+/// Collapses the blocks covered by a cell into a single material. The cell is solid
+/// as soon as any of its blocks is, and takes the material of its topmost solid block
+/// so surfaces keep their grass from far away.
+pub fn sample_cell(blocks: &Blocks, cell: Vector3<i32>, lod: usize) -> MaterialType {
+    let factor = LOD_FACTORS[lod];
+    let (x0, y0, z0) = (cell.x as usize * factor, cell.y as usize * factor, cell.z as usize * factor);
+
+    for y in (y0..(y0 + factor).min(CHUNK_Y_SIZE)).rev() {
+        for x in x0..x0 + factor {
+            for z in z0..z0 + factor {
+                let material_type = blocks[y][x][z].read().unwrap().material_type;
+                if material_type != MaterialType::AIR {
+                    return material_type;
+                }
+            }
+        }
+    }
+
+    MaterialType::AIR
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::scene::terrain::{block::Block, chunk::Blocks};
+
+    use super::*;
+
+    fn blocks_with(solid: &[([usize; 3], MaterialType)]) -> Blocks {
+        let mut blocks: Blocks = (0..CHUNK_Y_SIZE).map(|y| (0..CHUNK_AREA).map(|x| (0..CHUNK_AREA).map(|z| {
+            Arc::new(RwLock::new(Block::new(MaterialType::AIR, [x as i32, y as i32, z as i32], [0, 0, 0])))
+        }).collect()).collect()).collect();
+        for &([x, y, z], material_type) in solid {
+            blocks[y][x][z] = Arc::new(RwLock::new(Block::new(material_type, [x as i32, y as i32, z as i32], [0, 0, 0])));
+        }
+        blocks
+    }
+
+    #[test]
+    fn loaded_chunks_reach_coarser_levels() {
+        let view_radius = CHUNKS_VIEW_SIZE as i32 / 2;
+        assert_eq!(lod_for_distance(0), 0);
+        assert_eq!(lod_for_distance(FULL_RESOLUTION_DISTANCE), 0);
+        assert!(lod_for_distance(view_radius) > 0, "no loaded chunk is ever downsampled");
+
+        assert_eq!(lod_distances(2), [2, 3, 4]);
+        assert_eq!(lod_distances(10), [2, 5, 8]);
+        for distance in 0..16 {
+            assert!(lod_for_distance(distance) <= lod_for_distance(distance + 1));
+        }
+        assert_eq!(lod_for_distance(100), LOD_FACTORS.len() - 1);
+    }
+
+    #[test]
+    fn top_cells_are_clamped_to_the_chunk() {
+        assert_eq!(cells_per_chunk(0), Vector3::new(16, 100, 16));
+        // 100 blocks high: 12 full cells of 8 and a partial one of 4
+        assert_eq!(cells_per_chunk(3), Vector3::new(2, 13, 2));
+        assert_eq!(cell_size(Vector3::new(0, 11, 0), 3), [8, 8, 8]);
+        assert_eq!(cell_size(Vector3::new(1, 12, 1), 3), [8, 4, 8]);
+        assert_eq!(cells_per_chunk(2).y, 25);
+        assert_eq!(cell_size(Vector3::new(0, 24, 0), 2), [4, 4, 4]);
+
+        assert!(cell_in_bounds(Vector3::new(1, 12, 1), 3));
+        assert!(!cell_in_bounds(Vector3::new(0, 13, 0), 3));
+        assert!(!cell_in_bounds(Vector3::new(-1, 0, 0), 3));
+    }
+
+    #[test]
+    fn cells_take_their_topmost_solid_block() {
+        let blocks = blocks_with(&[
+            ([1, 0, 1], MaterialType::ROCK),
+            ([0, 1, 0], MaterialType::DIRT),
+            ([1, 1, 0], MaterialType::GRASS),
+            // in the partial top cell at lod 3
+            ([15, 99, 15], MaterialType::LEAVES),
+        ]);
+
+        assert_eq!(sample_cell(&blocks, Vector3::new(0, 0, 0), 1), MaterialType::DIRT);
+        assert_eq!(sample_cell(&blocks, Vector3::new(0, 0, 0), 0), MaterialType::AIR);
+        assert_eq!(sample_cell(&blocks, Vector3::new(1, 0, 1), 0), MaterialType::ROCK);
+        assert_eq!(sample_cell(&blocks, Vector3::new(1, 1, 1), 1), MaterialType::AIR);
+        assert_eq!(sample_cell(&blocks, Vector3::new(1, 12, 1), 3), MaterialType::LEAVES);
+        assert_eq!(sample_cell(&blocks, Vector3::new(0, 12, 0), 3), MaterialType::AIR);
+    }
+}
@@ -2,48 +2,93 @@ pub mod block;
 pub mod chunk;
 pub mod noise;
 pub mod biomes;
-use std::{collections::VecDeque, sync::{Arc, Barrier, RwLock}};
+pub mod lod;
+pub mod light;
+use std::{collections::VecDeque, sync::{Arc, RwLock}};
 
-use crate::render::{atlas::{Atlas, MaterialType}, mesh::Mesh, model::DynamicModel, pipelines::terrain::{BlockVertex, TerrainPipeline}, renderer::{Draw, Renderer}};
+use crate::render::{atlas::{BlockTextures, MaterialType}, arena::MeshArena, consts::Consts, pipelines::terrain::{BlockVertex, ChunkOrigin, RenderLayer, TerrainPipeline, TerrainShadowPipeline}, renderer::{catch_validation_errors, Draw, Renderer}};
 use crate::render::pipelines::GlobalsLayouts;
-use self::chunk::{generate_chunk, Blocks, CHUNK_AREA, CHUNK_Y_SIZE, Chunk, pos_in_chunk_bounds};
+use crate::resource_pack::{create_shader_module, ResourcePacks};
+use crate::settings::GraphicsSettings;
+use self::light::{pack_light, LightMap, LightVolume, MAX_LIGHT};
+use self::chunk::{chunk_offset_of, generate_chunk, Blocks, CHUNK_AREA, CHUNK_Y_SIZE, Chunk, LayerMeshes, pos_in_chunk_bounds};
 
 
 use biomes::{MOUNTAIN_PARAMS, PRAIRIE_PARAMS};
-use block::Block;
-use cgmath::{EuclideanSpace, Point3, Vector3};
+use block::{Block, Quad, QuadSide};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
 use chunk::local_pos_to_world;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use wgpu::Queue;
 
 
 pub const LAND_LEVEL: usize = 9;
-pub const CHUNKS_VIEW_SIZE: usize = 2;
+/// Chunks loaded along each horizontal axis, centred on the player. Each loaded chunk
+/// costs about 7 MB of blocks in memory and 704 KiB of mesh arena on the GPU, and
+/// generating them is most of the startup time, so this grows both quadratically.
+pub const CHUNKS_VIEW_SIZE: usize = 5;
 pub const CHUNKS_ARRAY_SIZE: usize = CHUNKS_VIEW_SIZE * CHUNKS_VIEW_SIZE;
+/// Horizontal distance from the player terrain is always loaded up to.
+pub const RENDER_DISTANCE: f32 = (CHUNKS_VIEW_SIZE / 2 * CHUNK_AREA) as f32;
+
+// chunk indices are u16 so a chunk mesh can't address more vertices than this, the
+// arena is sized for one such mesh per chunk and the layers of a chunk share it
+const MAX_CHUNK_VERTICES: usize = u16::MAX as usize + 1;
+const MAX_CHUNK_INDICES: usize = MAX_CHUNK_VERTICES / 4 * 6;
+
+// translucent quads of chunks closer than this to the eye are sorted again when it moves
+const TRANSLUCENT_SORT_DISTANCE: f64 = CHUNK_AREA as f64;
+
+// light of the faces of downsampled chunks
+const DISTANT_LIGHT: u32 = pack_light(0, MAX_LIGHT) as u32;
+
 
 
 
 
 pub struct Terrain {
-    pipeline: wgpu::RenderPipeline,
-    atlas: Atlas,
+    pipelines: Vec<wgpu::RenderPipeline>,
+    wireframe_pipelines: Vec<wgpu::RenderPipeline>,
+    // opaque and cutout layers into the shadow map
+    shadow_pipelines: Vec<wgpu::RenderPipeline>,
+    wireframe: bool,
+    textures: BlockTextures,
     pub chunks: Vec<Arc<RwLock<Chunk>>>,
     chunk_indices: Arc<RwLock<[Option<usize>; CHUNKS_ARRAY_SIZE]>>,
     free_chunk_indices: Arc<RwLock<VecDeque<usize>>>,
     updated_indices: Arc<RwLock<[bool; CHUNKS_ARRAY_SIZE]>>,
     center_offset: Vector3<i32>,
     chunks_origin: Vector3<i32>,
-    chunk_models: Vec<DynamicModel<BlockVertex>>
-
-
+    chunk_meshes: MeshArena<BlockVertex>,
+    // origin of the chunk at every pool index, vertices are relative to it
+    chunk_origins: Consts<ChunkOrigin>,
+    chunk_origins_bind_group: wgpu::BindGroup,
+    multi_draw: bool,
+    // position translucent meshes were last sorted from
+    eye: Point3<f64>,
+    // pool indices of the chunks holding translucent geometry, furthest first
+    translucent_order: Vec<usize>,
 }
 
 
 impl Terrain {
-    pub fn new(renderer: &Renderer) -> Self {
+    pub fn new(renderer: &Renderer, packs: &ResourcePacks, settings: &GraphicsSettings) -> Self {
         let global_layouts = GlobalsLayouts::new(&renderer.device);
-        let atlas = Atlas::new(&renderer.device, &renderer.queue, &global_layouts).unwrap();
-        let mut chunk_models = vec![];
+        let textures = BlockTextures::new(&renderer.device, &renderer.queue, &global_layouts, packs, settings).unwrap();
+        let chunk_meshes = MeshArena::new(
+            &renderer.device,
+            CHUNKS_ARRAY_SIZE * RenderLayer::ALL.len(),
+            CHUNKS_ARRAY_SIZE * MAX_CHUNK_VERTICES,
+            CHUNKS_ARRAY_SIZE * MAX_CHUNK_INDICES,
+        );
+        let chunk_origins = Consts::new(&renderer.device, CHUNKS_ARRAY_SIZE);
+        let chunk_origins_bind_group = renderer.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Chunk origins"),
+            layout: &global_layouts.chunk_origins,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: chunk_origins.buf().as_entire_binding(),
+            }],
+        });
         let mut chunks:Vec<Arc<RwLock<Chunk>>> = Vec::default();
         let chunk_indices: [Option<usize>; CHUNKS_ARRAY_SIZE] = [None; CHUNKS_ARRAY_SIZE];
         let updated_indices = Arc::new(RwLock::new([false; CHUNKS_ARRAY_SIZE]));
@@ -53,10 +98,6 @@ impl Terrain {
         for x in 0..CHUNKS_ARRAY_SIZE {
             //println!("initial x from new terrain: {:?}", x);
             chunks.push(Arc::new(RwLock::new(Chunk::new([0, 0, 0]))));
-            let mesh = Mesh::new(); //empty mesh
-            let mut chunk_model = DynamicModel::new(&renderer.device, (CHUNK_AREA ^ 2) * CHUNK_Y_SIZE * 24);
-            chunk_model.update(&renderer.queue, &mesh, 0);
-            chunk_models.push(chunk_model);
             free_chunk_indices.push_back(x);
 
 
@@ -65,17 +106,8 @@ impl Terrain {
         
 
 
-        let shader = renderer.device.create_shader_module(
-            wgpu::include_wgsl!("../../../assets/shaders/shader.wgsl")
-        );
-
-
-        let terrain_pipeline = TerrainPipeline::new(
-            &renderer.device,
-            &global_layouts,
-            shader,
-            &renderer.config
-        );
+        let (pipelines, wireframe_pipelines) = Self::create_pipelines(renderer, &global_layouts, &textures, packs).unwrap();
+        let shadow_pipelines = Self::create_shadow_pipelines(renderer, &global_layouts, &textures, packs).unwrap();
 
 
         let center_offset = Vector3::new(0, 0, 0);
@@ -83,10 +115,18 @@ impl Terrain {
 
 
         let mut terrain = Self {
-            pipeline: terrain_pipeline.pipeline,
-            atlas,
+            pipelines,
+            wireframe_pipelines,
+            shadow_pipelines,
+            wireframe: false,
+            textures,
             chunks,
-            chunk_models,
+            chunk_meshes,
+            chunk_origins,
+            chunk_origins_bind_group,
+            multi_draw: renderer.supports_multi_draw_indirect(),
+            eye: Point3::new(0.0, 0.0, 0.0),
+            translucent_order: Vec::new(),
             center_offset,
             chunks_origin,
             updated_indices,
@@ -110,12 +150,101 @@ impl Terrain {
 
 
         println!("about to load first chunks");
-        terrain.load_empty_chunks(&renderer.queue);
+        terrain.load_empty_chunks(renderer);
 
 
         terrain
     }
 
+    /// Fill and wireframe pipelines of every render layer, from the terrain shader of the packs.
+    fn create_pipelines(
+        renderer: &Renderer,
+        global_layouts: &GlobalsLayouts,
+        textures: &BlockTextures,
+        packs: &ResourcePacks,
+    ) -> anyhow::Result<(Vec<wgpu::RenderPipeline>, Vec<wgpu::RenderPipeline>)> {
+        // the shader sizes its array of chunk origins to the chunk pool
+        let slots = CHUNKS_ARRAY_SIZE.to_string();
+        let mut defines = vec![("CHUNK_SLOTS", slots.as_str())];
+        defines.extend_from_slice(textures.shader_defines());
+        let shader = create_shader_module(&renderer.device, packs, "shader.wgsl", &defines)?;
+        defines.push(("WIREFRAME", ""));
+        let wireframe_shader = create_shader_module(&renderer.device, packs, "shader.wgsl", &defines)?;
+
+        // adapters without line rasterization get the wireframe shading on filled faces
+        let line_mode = if renderer.supports_polygon_mode_line() { wgpu::PolygonMode::Line } else { wgpu::PolygonMode::Fill };
+        let create_pipelines = |shader, polygon_mode| RenderLayer::ALL.iter().map(|&layer| {
+            TerrainPipeline::new(
+                &renderer.device,
+                global_layouts,
+                textures.bind_group_layout(global_layouts),
+                shader,
+                layer,
+                polygon_mode,
+                renderer.sample_count(),
+            ).pipeline
+        }).collect();
+
+        Ok((
+            create_pipelines(&shader, wgpu::PolygonMode::Fill),
+            create_pipelines(&wireframe_shader, line_mode),
+        ))
+    }
+
+    /// Depth only pipelines of the layers casting shadows, from the shadow shader of the packs.
+    fn create_shadow_pipelines(
+        renderer: &Renderer,
+        global_layouts: &GlobalsLayouts,
+        textures: &BlockTextures,
+        packs: &ResourcePacks,
+    ) -> anyhow::Result<Vec<wgpu::RenderPipeline>> {
+        let slots = CHUNKS_ARRAY_SIZE.to_string();
+        let mut defines = vec![("CHUNK_SLOTS", slots.as_str())];
+        defines.extend_from_slice(textures.shader_defines());
+        let shader = create_shader_module(&renderer.device, packs, "shadow.wgsl", &defines)?;
+
+        Ok([RenderLayer::OPAQUE, RenderLayer::CUTOUT].iter().map(|&layer| {
+            TerrainShadowPipeline::new(&renderer.device, global_layouts, textures.bind_group_layout(global_layouts), &shader, layer).pipeline
+        }).collect())
+    }
+
+
+    /// Rebuild the pipelines from the current shaders, keeping the old ones if they fail
+    /// to compile.
+    pub fn reload_shaders(&mut self, renderer: &Renderer, packs: &ResourcePacks) -> anyhow::Result<()> {
+        let global_layouts = GlobalsLayouts::new(&renderer.device);
+        let (pipelines, wireframe_pipelines, shadow_pipelines) = catch_validation_errors(&renderer.device, &renderer.runtime, || {
+            let (pipelines, wireframe_pipelines) = Self::create_pipelines(renderer, &global_layouts, &self.textures, packs)?;
+            let shadow_pipelines = Self::create_shadow_pipelines(renderer, &global_layouts, &self.textures, packs)?;
+            Ok((pipelines, wireframe_pipelines, shadow_pipelines))
+        })?;
+        self.pipelines = pipelines;
+        self.wireframe_pipelines = wireframe_pipelines;
+        self.shadow_pipelines = shadow_pipelines;
+        Ok(())
+    }
+
+    /// Rebuild the block textures and the pipelines from other resource packs. Every
+    /// loaded chunk is remeshed afterwards since meshes bake the previous layers.
+    pub fn reload_resources(&mut self, renderer: &Renderer, packs: &ResourcePacks, settings: &GraphicsSettings) -> anyhow::Result<()> {
+        let global_layouts = GlobalsLayouts::new(&renderer.device);
+        let (textures, pipelines, wireframe_pipelines, shadow_pipelines) = catch_validation_errors(&renderer.device, &renderer.runtime, || {
+            let textures = BlockTextures::new(&renderer.device, &renderer.queue, &global_layouts, packs, settings)?;
+            let (pipelines, wireframe_pipelines) = Self::create_pipelines(renderer, &global_layouts, &textures, packs)?;
+            let shadow_pipelines = Self::create_shadow_pipelines(renderer, &global_layouts, &textures, packs)?;
+            Ok((textures, pipelines, wireframe_pipelines, shadow_pipelines))
+        })?;
+        self.textures = textures;
+        self.pipelines = pipelines;
+        self.wireframe_pipelines = wireframe_pipelines;
+        self.shadow_pipelines = shadow_pipelines;
+
+        let loaded: Vec<usize> = self.chunk_indices.read().unwrap().iter().flatten().copied().collect();
+        self.remesh_chunks(&loaded, renderer);
+        Ok(())
+    }
+
+
     fn get_chunk_at_offset(&self, offset: Vector3<i32>) -> Option<Arc<RwLock<Chunk>>> {
         if self.chunk_in_bounds(offset) {
             let index = self.get_chunk_world_index(offset);
@@ -128,27 +257,51 @@ impl Terrain {
 
 
 
-    pub fn load_empty_chunks(&mut self, queue: &Queue) {
+    pub fn load_empty_chunks(&mut self, renderer: &Renderer) {
+
+        println!("chunks array size: {:?}", CHUNKS_ARRAY_SIZE);
+
+        let loaded = self.generate_empty_chunks();
+
+        // meshes bake the light, so it's spread before meshing. Neighbors lit by the
+        // new chunks need a new mesh as well.
+        let offsets: Vec<[i32; 2]> = loaded.iter().map(|&i| {
+            let offset = self.chunks[i].read().unwrap().offset;
+            [offset[0], offset[2]]
+        }).collect();
+        let mut stale = self.update_light(|volume| {
+            for &offset in &offsets {
+                volume.light_chunk(offset);
+            }
+        });
+        stale.retain(|i| !loaded.contains(i));
 
-        let chunks_to_update: usize = (0..CHUNKS_ARRAY_SIZE)
-            .filter(|&i| self.chunk_indices.read().unwrap()[i].is_none())
-            .count();
+        loaded.into_par_iter().chain(stale).for_each(|new_index| {
+            let meshes = self.mesh_chunk(new_index);
+            self.chunks[new_index].write().unwrap().meshes = meshes;
 
-        let barrier = Arc::new(Barrier::new(chunks_to_update));
+            // Mark this index as updated
+            self.updated_indices.write().unwrap()[new_index] = true;
+        });
+    
 
-        println!("chunks array size: {:?}", CHUNKS_ARRAY_SIZE);
+        self.upload_updated_meshes(renderer);
 
-        (0..CHUNKS_ARRAY_SIZE).into_par_iter().for_each(|i| { 
-            let c = Arc::clone(&barrier); // Clonar la referencia a la barrera para cada hilo
 
-            
+        println!("---------------------------------");
+    }
 
 
+    /// Generate the blocks of every chunk in view that isn't loaded yet, returns their
+    /// pool indices. Every chunk is generated before any is meshed since meshing reads
+    /// the blocks of the neighbors. Callers mesh them in a second pass rather than
+    /// waiting on a barrier inside this parallel loop, which deadlocks when the thread
+    /// pool has fewer threads than chunks to load.
+    fn generate_empty_chunks(&self) -> Vec<usize> {
+        (0..CHUNKS_ARRAY_SIZE).into_par_iter().filter_map(|i| {
             let chunk_index = self.chunk_indices.read().unwrap()[i].clone();
 
             
-
-            
             if let None = chunk_index {
 
                 
@@ -163,52 +316,254 @@ impl Terrain {
 
 
                     self.chunks[new_index].write().unwrap().offset = chunk_offset.into();
+                    self.chunks[new_index].write().unwrap().lod = self.chunk_lod(chunk_offset);
                     generate_chunk(
                         &mut self.chunks[new_index].write().unwrap().blocks,
                         chunk_offset.into(),
                         892984781,
                         &PRAIRIE_PARAMS
                     );
-                    println!("barrera alcaza");
                     
                     self.chunk_indices.write().unwrap()[i] = Some(new_index);
+                    Some(new_index)
+                } else {
+                    panic!("Error: No free space for chunk")
+                }
+            } else {
+                None
+            }
+        }).collect()
+    }
 
-                    c.wait();
-                    let mesh = self.update_mesh(&self.chunks[new_index].read().unwrap().blocks, &self.chunks[new_index].read().unwrap().offset);
-                    self.chunks[new_index].write().unwrap().mesh = mesh;
 
-                    // Mark this index as updated
-                    self.updated_indices.write().unwrap()[new_index] = true;
+    /// Lock every loaded chunk to update their light, returns the pool indices of the
+    /// chunks whose light changed.
+    fn update_light(&self, update: impl FnOnce(&mut LightVolume)) -> Vec<usize> {
+        let loaded: Vec<usize> = self.chunk_indices.read().unwrap().iter().flatten().copied().collect();
+        let changed = {
+            let mut volume = LightVolume::new(loaded.iter().map(|&i| self.chunks[i].write().unwrap()));
+            update(&mut volume);
+            volume.changed_chunks()
+        };
 
+        let chunk_indices = self.chunk_indices.read().unwrap();
+        changed.into_iter()
+            .map(|[x, z]| Vector3::new(x, 0, z))
+            .filter(|&offset| self.chunk_in_bounds(offset))
+            .filter_map(|offset| chunk_indices[self.get_chunk_world_index(offset)])
+            .collect()
+    }
 
-                } else {
-                    panic!("Error: No free space for chunk")
-                }
+
+    /// Replace the block at a world position, then relight and remesh the chunks it
+    /// affects. Returns false if the position isn't in a loaded chunk.
+    pub fn set_block(&mut self, renderer: &Renderer, world_pos: Vector3<i32>, material_type: MaterialType) -> bool {
+        if world_pos.y < 0 || world_pos.y >= CHUNK_Y_SIZE as i32 {
+            return false;
+        }
+        let chunk_offset = Self::world_pos_to_chunk_offset(world_pos.cast().unwrap());
+        let Some(chunk_index) = self.get_chunk_index(chunk_offset) else { return false };
+
+        let local_pos = world_pos - Vector3::new(chunk_offset.x * CHUNK_AREA as i32, 0, chunk_offset.z * CHUNK_AREA as i32);
+        {
+            let chunk = self.chunks[chunk_index].read().unwrap();
+            let mut block = chunk.blocks[local_pos.y as usize][local_pos.x as usize][local_pos.z as usize].write().unwrap();
+            if block.material_type == material_type {
+                return true;
             }
-        });
-    
+            block.update(material_type, chunk.offset);
+        }
+
+        let mut stale = self.update_light(|volume| volume.block_changed(world_pos, material_type));
+        // the faces of the neighbors touching the block appear or disappear
+        for side in [QuadSide::RIGHT, QuadSide::LEFT, QuadSide::FRONT, QuadSide::BACK] {
+            let neighbor = Self::world_pos_to_chunk_offset((world_pos + side.to_vec()).cast().unwrap());
+            stale.extend(self.get_chunk_index(neighbor));
+        }
+        stale.push(chunk_index);
+        stale.sort();
+        stale.dedup();
+
+        self.remesh_chunks(&stale, renderer);
+        true
+    }
+
+
+    // pool index of the loaded chunk at a chunk offset
+    fn get_chunk_index(&self, chunk_offset: Vector3<i32>) -> Option<usize> {
+        if self.chunk_in_bounds(chunk_offset) {
+            self.chunk_indices.read().unwrap()[self.get_chunk_world_index(chunk_offset)]
+        } else {
+            None
+        }
+    }
+
 
+    fn upload_updated_meshes(&mut self, renderer: &Renderer) {
         (0..CHUNKS_ARRAY_SIZE).for_each(|i| {
 
             if self.updated_indices.read().unwrap()[i] {
-                self.chunk_models[i].update(queue, &self.chunks[i].read().unwrap().mesh, 0);
+                {
+                    let mut chunk = self.chunks[i].write().unwrap();
+                    let eye = (self.eye - chunk_origin(chunk.offset)).cast().unwrap();
+                    chunk.meshes[RenderLayer::TRANSLUCENT as usize].sort_quads_back_to_front(Point3::from_vec(eye));
+                    for layer in RenderLayer::ALL {
+                        if !self.chunk_meshes.upload(&renderer.device, &renderer.queue, Self::layer_slot(layer, i), &chunk.meshes[layer as usize]) {
+                            log::warn!("no space left in the chunk mesh arena for chunk {:?}", chunk.offset);
+                        }
+                    }
+                }
+                self.write_chunk_origin(renderer, i);
                 self.updated_indices.write().unwrap()[i] = false;
                 //println!("selected to update")
             }
 
         });
+    }
 
 
-        println!("---------------------------------");
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.wireframe = wireframe;
+    }
+
+
+    /// Seconds after which every block texture animation starts over.
+    pub fn animation_period(&self) -> f64 {
+        self.textures.animation_period
+    }
+
+
+    /// Offsets of every chunk in view, loaded or not.
+    pub fn chunk_offsets(&self) -> Vec<Vector3<i32>> {
+        (0..CHUNKS_ARRAY_SIZE).map(|i| self.get_chunk_offset(i)).collect()
+    }
+
+
+    // arena slots are grouped by layer so each layer is a contiguous range of draws
+    fn layer_slot(layer: RenderLayer, chunk_index: usize) -> usize {
+        layer as usize * CHUNKS_ARRAY_SIZE + chunk_index
+    }
+
+
+    // origin of the chunk at a pool index, relative to the chunk the terrain is drawn around
+    fn write_chunk_origin(&mut self, renderer: &Renderer, index: usize) {
+        let offset = Vector3::from(self.chunks[index].read().unwrap().offset) - self.center_offset;
+        let origin = (offset * CHUNK_AREA as i32).cast::<f32>().unwrap();
+        self.chunk_origins.update(&renderer.queue, &[ChunkOrigin { origin: [origin.x, origin.y, origin.z, 0.0] }], index);
+    }
+
+
+    /// Re-sort translucent geometry once the eye has moved to another block, and order
+    /// the chunks so the furthest ones are blended first.
+    fn sort_translucent(&mut self, renderer: &Renderer, eye: Point3<f64>) {
+        let mut order: Vec<usize> = (0..CHUNKS_ARRAY_SIZE)
+            .filter(|&i| !self.chunks[i].read().unwrap().meshes[RenderLayer::TRANSLUCENT as usize].indices().is_empty())
+            .collect();
+
+        let block_of = |p: Point3<f64>| p.map(|c| c.floor() as i64);
+        if block_of(eye) != block_of(self.eye) {
+            self.eye = eye;
+            // the quads of further chunks keep the order they were sorted in when meshed,
+            // the eye moving a block barely changes it
+            for &i in &order {
+                if chunk_box_distance(self.chunks[i].read().unwrap().offset, eye) > TRANSLUCENT_SORT_DISTANCE {
+                    continue;
+                }
+                let mut chunk = self.chunks[i].write().unwrap();
+                let local_eye = (eye - chunk_origin(chunk.offset)).cast().unwrap();
+                let mesh = &mut chunk.meshes[RenderLayer::TRANSLUCENT as usize];
+                mesh.sort_quads_back_to_front(Point3::from_vec(local_eye));
+                if !self.chunk_meshes.upload(&renderer.device, &renderer.queue, Self::layer_slot(RenderLayer::TRANSLUCENT, i), mesh) {
+                    log::warn!("no space left in the chunk mesh arena for chunk {:?}", chunk.offset);
+                }
+            }
+        }
+
+        let chunk_distance = |i: &usize| {
+            let offset = self.chunks[*i].read().unwrap().offset;
+            let center = Point3::new(
+                (offset[0] as f64 + 0.5) * CHUNK_AREA as f64,
+                eye.y,
+                (offset[2] as f64 + 0.5) * CHUNK_AREA as f64,
+            );
+            (center - eye).magnitude2()
+        };
+        order.sort_by(|a, b| chunk_distance(b).total_cmp(&chunk_distance(a)));
+        self.translucent_order = order;
+    }
+
+
+    fn chunk_lod(&self, chunk_offset: Vector3<i32>) -> usize {
+        lod::lod_for_distance(lod::chunk_distance(chunk_offset, self.center_offset))
+    }
+
+
+    // meshes the chunk at the given pool index with the resolution of its lod
+    fn mesh_chunk(&self, index: usize) -> LayerMeshes {
+        let chunk = self.chunks[index].read().unwrap();
+        let mut meshes = if chunk.lod == 0 {
+            self.update_mesh(&chunk.blocks, &chunk.light, &chunk.offset)
+        } else {
+            self.update_lod_mesh(&chunk.blocks, &chunk.offset, chunk.lod)
+        };
+        // vertices find the origin of their chunk by its pool index
+        for vertex in meshes.iter_mut().flat_map(|mesh| mesh.verts.iter_mut()) {
+            *vertex = vertex.with_chunk(index as u32);
+        }
+        meshes
+    }
+
+
+    /// Recomputes the lod of every loaded chunk around the new center. Returns the pool
+    /// indices of the chunks that need a new mesh, either because their own lod changed or
+    /// because a neighbor's did and the seam between them has to be rebuilt.
+    fn refresh_lods(&mut self) -> Vec<usize> {
+        let mut changed_offsets = Vec::new();
+        for i in 0..CHUNKS_ARRAY_SIZE {
+            if let Some(chunk_index) = self.chunk_indices.read().unwrap()[i] {
+                let mut chunk = self.chunks[chunk_index].write().unwrap();
+                let new_lod = self.chunk_lod(chunk.offset.into());
+                if new_lod != chunk.lod {
+                    chunk.lod = new_lod;
+                    changed_offsets.push(Vector3::from(chunk.offset));
+                }
+            }
+        }
+
+        let mut stale = Vec::new();
+        for offset in changed_offsets {
+            for neighbor in [Vector3::new(0, 0, 0), Vector3::new(1, 0, 0), Vector3::new(-1, 0, 0), Vector3::new(0, 0, 1), Vector3::new(0, 0, -1)] {
+                let neighbor_offset = offset + neighbor;
+                if !self.chunk_in_bounds(neighbor_offset) {
+                    continue;
+                }
+                if let Some(chunk_index) = self.chunk_indices.read().unwrap()[self.get_chunk_world_index(neighbor_offset)] {
+                    if !stale.contains(&chunk_index) {
+                        stale.push(chunk_index);
+                    }
+                }
+            }
+        }
+        stale
+    }
+
+
+    fn remesh_chunks(&mut self, indices: &[usize], renderer: &Renderer) {
+        indices.into_par_iter().for_each(|&i| {
+            let meshes = self.mesh_chunk(i);
+            self.chunks[i].write().unwrap().meshes = meshes;
+            self.updated_indices.write().unwrap()[i] = true;
+        });
+
+        self.upload_updated_meshes(renderer);
     }
 
 
 
-    pub fn update_mesh(&self, blocks: &Blocks, offset: &[i32; 3]) -> Mesh<BlockVertex> {
+    pub fn update_mesh(&self, blocks: &Blocks, light: &LightMap, offset: &[i32; 3]) -> LayerMeshes {
 
 
-        let mut verts =Vec::new();
-        let mut indices = Vec::new();
+        let mut meshes = LayerMeshes::default();
         for y in 0.. CHUNK_Y_SIZE{
             for z in 0..CHUNK_AREA {
                 for x in 0..CHUNK_AREA {
@@ -225,41 +580,125 @@ impl Terrain {
 
                     for quad in block.quads.iter() {
                         let neighbor_pos: Vector3<i32> = block.get_vec_position() + quad.side.to_vec();
-                        let neighbor_exists = self.neighbor_exists(&neighbor_pos, blocks, offset);
+                        let neighbor_material = self.neighbor_material(&neighbor_pos, blocks, offset, 0);
+
+
+                        if !block.material_type.face_hidden_by(neighbor_material) {
+                            // a face is lit by the block in front of it
+                            let face_light = self.neighbor_light(&neighbor_pos, light, offset) as u32;
+                            let ao = self.face_ao(quad, block.get_vec_position(), blocks, offset);
+                            let layer = self.textures.face_layer(block.material_type, quad.side);
+                            block_vertices.extend(quad.vertices.iter().zip(ao).map(|(v, ao)| v.with_layer(layer).with_light(face_light).with_ao(ao)));
+                            if flip_quad(ao) {
+                                block_indices.extend_from_slice(&quad.get_flipped_indices(quad_counter));
+                            } else {
+                                block_indices.extend_from_slice(&quad.get_indices(quad_counter));
+                            }
+                            quad_counter += 1;
+                        }
+                    }
+                    let mesh = &mut meshes[block.material_type.render_layer() as usize];
+                    block_indices = block_indices.iter().map(|i| i + mesh.verts.len() as u16).collect();
+                    mesh.verts.extend(block_vertices);
+                    mesh.indices.extend(block_indices);
+                }
+            }
+        }
 
 
-                        if !neighbor_exists { //if neigbor does not exists, the current face is visible
-                            block_vertices.extend_from_slice(&quad.vertices);
-                            block_indices.extend_from_slice(&quad.get_indices(quad_counter));
-                            quad_counter += 1;
+        meshes
+    }
+
+
+    /// Meshes a chunk downsampled to the cell size of `lod`, every solid cell becomes a
+    /// box covering the blocks it was sampled from.
+    pub fn update_lod_mesh(&self, blocks: &Blocks, offset: &[i32; 3], lod: usize) -> LayerMeshes {
+        let mut meshes = LayerMeshes::default();
+        let cells = lod::cells_per_chunk(lod);
+        let factor = lod::LOD_FACTORS[lod] as i32;
+
+        for y in 0..cells.y {
+            for z in 0..cells.z {
+                for x in 0..cells.x {
+                    let cell = Vector3::new(x, y, z);
+                    let material_type = lod::sample_cell(blocks, cell, lod);
+                    if material_type == MaterialType::AIR {
+                        continue;
+                    }
+
+                    let position = [x * factor, y * factor, z * factor];
+                    let size = lod::cell_size(cell, lod);
+
+                    let mesh = &mut meshes[material_type.render_layer() as usize];
+                    for side in [QuadSide::TOP, QuadSide::BOTTOM, QuadSide::RIGHT, QuadSide::LEFT, QuadSide::FRONT, QuadSide::BACK] {
+                        if material_type.face_hidden_by(self.lod_neighbor_material(cell + side.to_vec(), blocks, offset, lod)) {
+                            continue;
                         }
+                        let quad = Quad::new_scaled(side, position, size);
+                        let layer = self.textures.face_layer(material_type, side);
+                        mesh.indices.extend(quad.get_indices(0).iter().map(|i| i + mesh.verts.len() as u16));
+                        // distant chunks aren't lit per block, they only get the sky
+                        mesh.verts.extend(quad.vertices.iter().map(|v| v.with_layer(layer).with_light(DISTANT_LIGHT)));
                     }
-                    block_indices = block_indices.iter().map(|i| i + verts.len() as u16).collect();
-                    verts.extend(block_vertices);
-                    indices.extend(block_indices);
                 }
             }
         }
 
+        meshes
+    }
+
+
+    // material of the neighboring cell, faces next to AIR are visible
+    fn lod_neighbor_material(&self, neighbor_cell: Vector3<i32>, blocks: &Blocks, chunk_offset: &[i32; 3], lod: usize) -> MaterialType {
+        if lod::cell_in_bounds(neighbor_cell, lod) {
+            return lod::sample_cell(blocks, neighbor_cell, lod);
+        }
+
+        let cells = lod::cells_per_chunk(lod);
+        if neighbor_cell.y < 0 || neighbor_cell.y >= cells.y {
+            return MaterialType::AIR;
+        }
 
-        Mesh { verts, indices }
+        let neighbor_chunk_offset = Vector3::new(
+            chunk_offset[0] + neighbor_cell.x.div_euclid(cells.x),
+            0,
+            chunk_offset[2] + neighbor_cell.z.div_euclid(cells.z),
+        );
+
+        match self.get_chunk_at_offset(neighbor_chunk_offset) {
+            Some(neighbor_chunk) => {
+                let neighbor_chunk = neighbor_chunk.read().unwrap();
+                // faces facing a chunk with another lod are always kept, they seal the seam
+                if neighbor_chunk.lod != lod {
+                    return MaterialType::AIR;
+                }
+                let local_cell = Vector3::new(neighbor_cell.x.rem_euclid(cells.x), neighbor_cell.y, neighbor_cell.z.rem_euclid(cells.z));
+                lod::sample_cell(&neighbor_chunk.blocks, local_cell, lod)
+            }
+            None => MaterialType::AIR,
+        }
     }
 
 
-    fn neighbor_exists(&self, neighbor_pos: &Vector3<i32>, blocks: &Blocks, chunk_offset: &[i32; 3]) -> bool {
+    // material of the neighboring block, missing neighbors count as AIR so the face is kept
+    fn neighbor_material(&self, neighbor_pos: &Vector3<i32>, blocks: &Blocks, chunk_offset: &[i32; 3], lod: usize) -> MaterialType {
         if pos_in_chunk_bounds(*neighbor_pos) {
             let neighbor_block = blocks[neighbor_pos.y as usize][neighbor_pos.x as usize][neighbor_pos.z as usize].read().unwrap();
-            return !(neighbor_block.material_type as u16 == MaterialType::AIR as u16);
+            return neighbor_block.material_type;
         } else {
 
             if neighbor_pos.y < 0 || neighbor_pos.y >= CHUNK_Y_SIZE as i32 {
-                return false;
+                return MaterialType::AIR;
             }
             let world_pos = local_pos_to_world(chunk_offset, neighbor_pos);
             let neighbor_chunk_offset = Self::world_pos_to_chunk_offset(world_pos);
             
             if let Some(neighbor_chunk) = self.get_chunk_at_offset(neighbor_chunk_offset) {
                 let neighbor_chunk = neighbor_chunk.read().unwrap();
+                // faces facing a chunk with another lod are always kept, they seal the seam
+                if neighbor_chunk.lod != lod {
+                    return MaterialType::AIR;
+                }
                 let local_pos = Vector3::new(
                     world_pos.x as i32 - (neighbor_chunk_offset[0] * CHUNK_AREA as i32),
                     world_pos.y as i32- (neighbor_chunk_offset[1] * CHUNK_Y_SIZE as i32),
@@ -268,20 +707,92 @@ impl Terrain {
 
                 if pos_in_chunk_bounds(local_pos) {
                     let neighbor_block = neighbor_chunk.blocks[local_pos.y as usize][local_pos.x as usize][local_pos.z as usize].read().unwrap();
-                    return !(neighbor_block.material_type as u16 == MaterialType::AIR as u16);
+                    return neighbor_block.material_type;
                 }
                 else {
                     //println!("{:?}",neighbor_chunk_offset);
                     println!("debug {:?}", local_pos);
                     println!("error esto no deberia ??");
-                    return false;
+                    return MaterialType::AIR;
                 }
                 
             }
             else {
                 println!("no encontro chunk")
             }
-            return false;
+            return MaterialType::AIR;
+        }
+    }
+
+
+    // Ambient occlusion of the vertices of a face, from the two blocks along the edges
+    // of every vertex and the one across its corner, in the layer in front of the face.
+    fn face_ao(&self, quad: &Quad, block_pos: Vector3<i32>, blocks: &Blocks, chunk_offset: &[i32; 3]) -> [u32; 4] {
+        let normal = quad.side.to_vec();
+        let front = block_pos + normal;
+        let solid = |pos: Vector3<i32>| !self.material_at(pos, blocks, chunk_offset).transmits_light();
+
+        quad.vertices.map(|vertex| {
+            // unit steps from the center of the face towards the vertex
+            let mut steps = [Vector3::new(0, 0, 0); 2];
+            let mut axis = 0;
+            for i in 0..3 {
+                if normal[i] == 0 {
+                    steps[axis][i] = if vertex.pos()[i] as i32 > block_pos[i] { 1 } else { -1 };
+                    axis += 1;
+                }
+            }
+
+            vertex_ao(solid(front + steps[0]), solid(front + steps[1]), || solid(front + steps[0] + steps[1]))
+        })
+    }
+
+
+    // material of any block next to the chunk, whatever the lod of its chunk, AIR
+    // outside of the loaded chunks
+    fn material_at(&self, pos: Vector3<i32>, blocks: &Blocks, chunk_offset: &[i32; 3]) -> MaterialType {
+        if pos_in_chunk_bounds(pos) {
+            return blocks[pos.y as usize][pos.x as usize][pos.z as usize].read().unwrap().material_type;
+        }
+        if pos.y < 0 || pos.y >= CHUNK_Y_SIZE as i32 {
+            return MaterialType::AIR;
+        }
+
+        let neighbor_chunk_offset = Self::world_pos_to_chunk_offset(local_pos_to_world(chunk_offset, &pos));
+        match self.get_chunk_at_offset(neighbor_chunk_offset) {
+            Some(neighbor_chunk) => {
+                let (x, z) = (pos.x.rem_euclid(CHUNK_AREA as i32) as usize, pos.z.rem_euclid(CHUNK_AREA as i32) as usize);
+                let neighbor_chunk = neighbor_chunk.read().unwrap();
+                let material_type = neighbor_chunk.blocks[pos.y as usize][x][z].read().unwrap().material_type;
+                material_type
+            }
+            None => MaterialType::AIR,
+        }
+    }
+
+
+    // packed light of the neighboring block, outside of the loaded chunks only the sky
+    // lights it
+    fn neighbor_light(&self, neighbor_pos: &Vector3<i32>, light: &LightMap, chunk_offset: &[i32; 3]) -> u8 {
+        if pos_in_chunk_bounds(*neighbor_pos) {
+            return light.packed(*neighbor_pos);
+        }
+        if neighbor_pos.y < 0 || neighbor_pos.y >= CHUNK_Y_SIZE as i32 {
+            return pack_light(0, if neighbor_pos.y < 0 { 0 } else { MAX_LIGHT });
+        }
+
+        let world_pos = local_pos_to_world(chunk_offset, neighbor_pos);
+        let neighbor_chunk_offset = Self::world_pos_to_chunk_offset(world_pos);
+        match self.get_chunk_at_offset(neighbor_chunk_offset) {
+            Some(neighbor_chunk) => {
+                let local_pos = Vector3::new(
+                    neighbor_pos.x.rem_euclid(CHUNK_AREA as i32),
+                    neighbor_pos.y,
+                    neighbor_pos.z.rem_euclid(CHUNK_AREA as i32),
+                );
+                neighbor_chunk.read().unwrap().light.packed(local_pos)
+            }
+            None => pack_light(0, MAX_LIGHT),
         }
     }
 
@@ -316,8 +827,14 @@ impl Terrain {
 
 
     //called every frame
-    pub fn update(&mut self, queue: &Queue, player_position: &Point3<f32>) {
-        let new_center_offset = Self::world_pos_to_chunk_offset(player_position.to_vec());
+    pub fn update(&mut self, renderer: &Renderer, player_position: &Point3<f64>) {
+        self.update_chunks(renderer, player_position);
+        self.sort_translucent(renderer, *player_position);
+    }
+
+
+    fn update_chunks(&mut self, renderer: &Renderer, player_position: &Point3<f64>) {
+        let new_center_offset = chunk_offset_of(*player_position);
         let new_chunk_origin = new_center_offset - Vector3::new(CHUNKS_VIEW_SIZE as i32 / 2, 0, CHUNKS_VIEW_SIZE as i32 / 2);
 
         if new_chunk_origin == self.chunks_origin {
@@ -327,10 +844,15 @@ impl Terrain {
         self.center_offset = new_center_offset;
         self.chunks_origin = new_chunk_origin;
         println!("chunks origin updated {:?}", self.chunks_origin);
+        // the terrain is drawn around its center
+        for i in 0..CHUNKS_ARRAY_SIZE {
+            self.write_chunk_origin(renderer, i);
+        }
 
         let chunk_indices_copy = self.chunk_indices.read().unwrap().clone();
         self.chunk_indices = Arc::new(RwLock::new([None; CHUNKS_ARRAY_SIZE]));
 
+        let mut unloaded = Vec::new();
         for i in 0..CHUNKS_ARRAY_SIZE {
             match chunk_indices_copy[i] {
                 Some(chunk_index) => {
@@ -341,34 +863,208 @@ impl Terrain {
                         self.chunk_indices.write().unwrap()[new_chunk_world_index] = Some(chunk_index);
                     } else {
                         self.free_chunk_indices.write().unwrap().push_back(chunk_index);
+                        unloaded.push(chunk_index);
                     }
                 }
                 None => {}
             }
         }
 
-        self.load_empty_chunks(queue);
+        let mut stale_chunks = self.update_light(|volume| {
+            for &chunk_index in &unloaded {
+                volume.unload_chunk(&self.chunks[chunk_index].read().unwrap());
+            }
+        });
+        for chunk_index in self.refresh_lods() {
+            if !stale_chunks.contains(&chunk_index) {
+                stale_chunks.push(chunk_index);
+            }
+        }
+        self.load_empty_chunks(renderer);
+        self.remesh_chunks(&stale_chunks, renderer);
     }
 
 
 }
 
+impl Terrain {
+    /// The translucent chunks, drawn apart from the rest of the terrain so they are
+    /// blended over everything opaque.
+    pub fn translucent(&self) -> TranslucentTerrain<'_> {
+        TranslucentTerrain(self)
+    }
+
+    fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, globals: &'a wgpu::BindGroup) -> &'a [wgpu::RenderPipeline] {
+        render_pass.set_bind_group(0, &self.textures.bind_group, &[]);
+        render_pass.set_bind_group(1, globals, &[]);
+        render_pass.set_bind_group(2, &self.chunk_origins_bind_group, &[]);
+        if self.wireframe { &self.wireframe_pipelines } else { &self.pipelines }
+    }
+}
+
 impl Draw for Terrain {
     fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, globals: &'a wgpu::BindGroup) -> Result<(), wgpu::Error> {
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, &self.atlas.bind_group, &[]);
-        render_pass.set_bind_group(1, &globals, &[]);
-        
-        for chunk_model in &self.chunk_models {
-                let vertex_buffer = chunk_model.vbuf().slice(..);
-                let index_buffer = chunk_model.ibuf().slice(..);
-                let num_indices = chunk_model.num_indices;
-
-                render_pass.set_vertex_buffer(0, vertex_buffer);
-                render_pass.set_index_buffer(index_buffer, wgpu::IndexFormat::Uint16);
-                render_pass.draw_indexed(0..num_indices as u32, 0, 0..1 as _);
+        let pipelines = self.bind(render_pass, globals);
+
+        for layer in [RenderLayer::OPAQUE, RenderLayer::CUTOUT] {
+            let first_slot = Terrain::layer_slot(layer, 0);
+            render_pass.set_pipeline(&pipelines[layer as usize]);
+            self.chunk_meshes.draw(render_pass, first_slot..first_slot + CHUNKS_ARRAY_SIZE, self.multi_draw);
         }
-        
+
+        Ok(())
+    }
+
+    // Translucent blocks let the sun through.
+    fn draw_shadow<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, cascade: &'a wgpu::BindGroup) -> Result<(), wgpu::Error> {
+        render_pass.set_bind_group(0, &self.textures.bind_group, &[]);
+        render_pass.set_bind_group(1, cascade, &[]);
+        render_pass.set_bind_group(2, &self.chunk_origins_bind_group, &[]);
+
+        for (pipeline, layer) in self.shadow_pipelines.iter().zip([RenderLayer::OPAQUE, RenderLayer::CUTOUT]) {
+            let first_slot = Terrain::layer_slot(layer, 0);
+            render_pass.set_pipeline(pipeline);
+            self.chunk_meshes.draw(render_pass, first_slot..first_slot + CHUNKS_ARRAY_SIZE, self.multi_draw);
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Translucent layer of the terrain, see `Terrain::translucent`.
+pub struct TranslucentTerrain<'a>(&'a Terrain);
+
+impl Draw for TranslucentTerrain<'_> {
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, globals: &'a wgpu::BindGroup) -> Result<(), wgpu::Error> {
+        let terrain = self.0;
+        let pipelines = terrain.bind(render_pass, globals);
+
+        // translucent chunks go one by one, back to front
+        render_pass.set_pipeline(&pipelines[RenderLayer::TRANSLUCENT as usize]);
+        let slots: Vec<usize> = terrain.translucent_order.iter().map(|&i| Terrain::layer_slot(RenderLayer::TRANSLUCENT, i)).collect();
+        terrain.chunk_meshes.draw_ordered(render_pass, &slots);
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+
+// world position of the first block of a chunk
+fn chunk_origin(offset: [i32; 3]) -> Point3<f64> {
+    Point3::new(offset[0] as f64, offset[1] as f64, offset[2] as f64) * CHUNK_AREA as f64
+}
+
+// horizontal distance from a point to the closest column of a chunk, 0 inside it
+fn chunk_box_distance(offset: [i32; 3], point: Point3<f64>) -> f64 {
+    let origin = chunk_origin(offset);
+    let outside = |p: f64, min: f64| (min - p).max(p - (min + CHUNK_AREA as f64)).max(0.0);
+    outside(point.x, origin.x).hypot(outside(point.z, origin.z))
+}
+
+// Ambient occlusion of a vertex from 0 to 3 given which blocks along its edges are solid,
+// the corner block only counts when one edge is open since two solid edges hide it.
+fn vertex_ao(side1: bool, side2: bool, corner: impl FnOnce() -> bool) -> u32 {
+    if side1 && side2 {
+        return 0;
+    }
+    3 - (side1 as u32 + side2 as u32 + corner() as u32)
+}
+
+// Whether a quad is split along the diagonal from vertex 1 to 3 rather than from 0 to 2,
+// the one with the least occlusion difference so the darkening is interpolated
+// symmetrically.
+fn flip_quad(ao: [u32; 4]) -> bool {
+    ao[0] + ao[2] < ao[1] + ao[3]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use crate::render::renderer::Renderer;
+    use crate::resource_pack::ResourcePacks;
+    use crate::settings::GraphicsSettings;
+
+    use super::*;
+
+    #[test]
+    fn two_solid_edges_hide_the_corner() {
+        assert_eq!(vertex_ao(false, false, || false), 3);
+        assert_eq!(vertex_ao(false, false, || true), 2);
+        assert_eq!(vertex_ao(true, false, || true), 1);
+        assert_eq!(vertex_ao(false, true, || false), 2);
+        assert_eq!(vertex_ao(true, true, || false), 0);
+        assert_eq!(vertex_ao(true, true, || panic!("corner sampled")), 0);
+    }
+
+    #[test]
+    fn quads_split_along_the_brighter_diagonal() {
+        assert!(!flip_quad([3, 3, 3, 3]));
+        // a dark vertex 0 or 2 keeps the diagonal from 1 to 3 lit
+        assert!(flip_quad([0, 3, 3, 3]));
+        assert!(flip_quad([3, 3, 1, 3]));
+        assert!(!flip_quad([3, 0, 3, 3]));
+        assert!(!flip_quad([3, 3, 3, 2]));
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn occlusion_reads_the_neighboring_chunk() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let renderer = Renderer::headless(64, 48, &runtime, false).expect("no wgpu adapter");
+        let terrain = Terrain::new(&renderer, &ResourcePacks::embedded(), &GraphicsSettings::default());
+        let chunk = terrain.get_chunk_at_offset(Vector3::new(0, 0, 0)).unwrap();
+        let neighbor = terrain.get_chunk_at_offset(Vector3::new(1, 0, 0)).unwrap();
+        let set_rock = |chunk: &RwLock<Chunk>, [x, y, z]: [usize; 3]| {
+            let chunk = chunk.read().unwrap();
+            chunk.blocks[y][x][z].write().unwrap().update(MaterialType::ROCK, chunk.offset);
+        };
+
+        // a block on the +x side of the chunk, high above the ground, with rocks in
+        // front of its top face on both sides of the border
+        set_rock(&chunk, [15, 90, 8]);
+        set_rock(&chunk, [15, 91, 7]);
+        set_rock(&neighbor, [0, 91, 8]);
+        set_rock(&neighbor, [0, 91, 9]);
+
+        let chunk = chunk.read().unwrap();
+        let block = chunk.blocks[90][15][8].read().unwrap();
+        let top = &block.quads[0];
+        let ao = terrain.face_ao(top, block.get_vec_position(), &chunk.blocks, &chunk.offset);
+        for (vertex, ao) in top.vertices.iter().zip(ao) {
+            let expected = match vertex.pos() {
+                [16, _, 8] => 0,
+                [16, _, 9] => 1,
+                [15, _, 8] => 2,
+                _ => 3,
+            };
+            assert_eq!(ao, expected, "vertex at {:?}", vertex.pos());
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn chunks_load_with_fewer_threads_than_chunks() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let renderer = Renderer::headless(64, 48, &runtime, false).expect("no wgpu adapter");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+        // loading on another thread so a deadlock fails the test instead of hanging it,
+        // the renderer comes back to be dropped on the thread that created it
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let loaded = pool.install(|| {
+                let terrain = Terrain::new(&renderer, &ResourcePacks::embedded(), &GraphicsSettings::default());
+                let loaded = terrain.chunk_indices.read().unwrap().iter().flatten().count();
+                loaded
+            });
+            sender.send((loaded, renderer)).unwrap();
+        });
+
+        let (loaded, _renderer) = receiver.recv_timeout(Duration::from_secs(600)).expect("loading the chunks didn't finish");
+        assert_eq!(loaded, CHUNKS_ARRAY_SIZE);
+    }
+}
@@ -0,0 +1,129 @@
+use std::f32::consts::TAU;
+
+use cgmath::{InnerSpace, Vector3};
+
+
+/// Rate world time advances at with a time scale of 1.
+pub const TICKS_PER_SECOND: f32 = 20.0;
+/// Length of a day, 20 minutes with a time scale of 1.
+pub const TICKS_PER_DAY: u64 = 24000;
+
+// Tilt of the sun path towards +z, so the sun isn't straight up at noon
+const SUN_TILT: f32 = 0.3;
+
+
+/// Time of the world in ticks, counted from the first sunrise. It only moves in whole
+/// ticks so everything driven by it steps the same way at any frame rate.
+#[derive(Clone, Debug)]
+pub struct WorldTime {
+    ticks: u64,
+    /// Multiplier of the tick rate, 0 stops the time.
+    scale: f32,
+    // fraction of a tick carried over to the next frame
+    pending: f32,
+}
+
+impl Default for WorldTime {
+    fn default() -> Self {
+        Self::from_ticks(0)
+    }
+}
+
+impl WorldTime {
+    pub fn from_ticks(ticks: u64) -> Self {
+        Self { ticks, scale: 1.0, pending: 0.0 }
+    }
+
+    pub fn ticks(&self) -> u64 { self.ticks }
+
+    pub fn scale(&self) -> f32 { self.scale }
+
+    /// Change how fast time runs, negative scales are clamped to 0.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.max(0.0);
+    }
+
+    /// Advance by the ticks elapsed during `dt`, returns how many there were.
+    pub fn advance(&mut self, dt: std::time::Duration) -> u64 {
+        self.pending += dt.as_secs_f32() * TICKS_PER_SECOND * self.scale;
+        let ticks = self.pending.floor();
+        self.pending -= ticks;
+        self.ticks += ticks as u64;
+        ticks as u64
+    }
+
+    /// Fraction of the current day, 0 at sunrise, 0.25 at noon, 0.5 at sunset and
+    /// 0.75 at midnight.
+    pub fn time_of_day(&self) -> f32 {
+        (self.ticks % TICKS_PER_DAY) as f32 / TICKS_PER_DAY as f32
+    }
+
+    /// Jump to a time of the current day, see `time_of_day`. Time never runs
+    /// backwards, earlier times are reached on the next day.
+    pub fn set_time_of_day(&mut self, time_of_day: f32) {
+        let target = (time_of_day.rem_euclid(1.0) * TICKS_PER_DAY as f32) as u64;
+        let day_start = self.ticks - self.ticks % TICKS_PER_DAY;
+        self.ticks = if day_start + target >= self.ticks { day_start + target } else { day_start + TICKS_PER_DAY + target };
+        self.pending = 0.0;
+    }
+
+    /// Direction towards the sun, rising at +x and setting at -x. The moon is opposite.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let (sin, cos) = (self.time_of_day() * TAU).sin_cos();
+        Vector3::new(cos, sin, SUN_TILT * sin).normalize()
+    }
+
+    /// Amount of sunlight from 0 at night to 1 during the day, fading while the sun
+    /// crosses the horizon.
+    pub fn daylight(&self) -> f32 {
+        let t = ((self.sun_direction().y + 0.1) * 4.0).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn fractions_of_a_tick_carry_over() {
+        let mut time = WorldTime::default();
+        // 2.5 ticks a second
+        time.set_scale(0.125);
+        assert_eq!(time.advance(Duration::from_secs(1)), 2);
+        assert_eq!(time.advance(Duration::from_secs(1)), 3);
+        assert_eq!(time.advance(Duration::from_secs(1)), 2);
+        assert_eq!(time.advance(Duration::from_millis(200)), 1);
+        assert_eq!(time.ticks(), 8);
+    }
+
+    #[test]
+    fn a_scale_of_zero_stops_the_time() {
+        let mut time = WorldTime::from_ticks(100);
+        time.set_scale(0.0);
+        assert_eq!(time.advance(Duration::from_secs(60)), 0);
+        time.set_scale(-2.0);
+        assert_eq!(time.scale(), 0.0);
+        assert_eq!(time.advance(Duration::from_secs(60)), 0);
+        assert_eq!(time.ticks(), 100);
+    }
+
+    #[test]
+    fn setting_the_time_of_day_never_runs_backwards() {
+        let mut time = WorldTime::from_ticks(TICKS_PER_DAY + TICKS_PER_DAY / 4);
+        // later today
+        time.set_time_of_day(0.5);
+        assert_eq!(time.ticks(), TICKS_PER_DAY + TICKS_PER_DAY / 2);
+        // earlier times wrap to the next day
+        time.set_time_of_day(0.25);
+        assert_eq!(time.ticks(), 2 * TICKS_PER_DAY + TICKS_PER_DAY / 4);
+        time.set_time_of_day(-0.75);
+        assert_eq!(time.ticks(), 2 * TICKS_PER_DAY + TICKS_PER_DAY / 4);
+        time.set_time_of_day(0.0);
+        assert_eq!(time.ticks(), 3 * TICKS_PER_DAY);
+        assert_eq!(time.time_of_day(), 0.0);
+    }
+}
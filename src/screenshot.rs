@@ -0,0 +1,79 @@
+use std::{path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use anyhow::*;
+
+
+/// Directory screenshots are written to, relative to the working directory.
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+
+/// Write a screenshot as a PNG named after the current UTC time, in `dir`.
+pub fn save(image: &image::RgbaImage, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let stamp = timestamp(SystemTime::now());
+    let mut path = dir.join(format!("{stamp}.png"));
+    // several screenshots in the same second
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{stamp}_{n}.png"));
+    }
+
+    image.save_with_format(&path, image::ImageFormat::Png)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+
+// `YYYY-MM-DD_HH.MM.SS` in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, day_secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}.{:02}.{:02}",
+        day_secs / 3600, day_secs / 60 % 60, day_secs % 60
+    )
+}
+
+// Gregorian date of a number of days since 1970-01-01, from Howard Hinnant's
+// `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn days_map_to_their_gregorian_date() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        // leap days, 2100 isn't a leap year
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(47540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn timestamps_are_utc_date_and_time() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01_00.00.00");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1709251199)), "2024-02-29_23.59.59");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1709251200)), "2024-03-01_00.00.00");
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+
+
+/// Options chosen when launching the game.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub graphics: GraphicsSettings,
+    /// Shader directory read before the resource packs and reloaded when its files
+    /// change, for shader development.
+    pub shader_dir: Option<PathBuf>,
+    /// Directory of the block textures packed at startup, the copies embedded in the
+    /// binary are used without one or when it's missing.
+    pub block_texture_dir: Option<PathBuf>,
+    /// Directory the world is saved to.
+    pub world_dir: PathBuf,
+    /// Speed of the day/night cycle, 1 is a 20 minute day and 0 stops it.
+    pub time_scale: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            graphics: GraphicsSettings::default(),
+            shader_dir: None,
+            block_texture_dir: Some(PathBuf::from("assets/textures/block")),
+            world_dir: PathBuf::from("saves/world"),
+            time_scale: 1.0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_args(args: &ArgMatches) -> Self {
+        let mut settings = Self::default();
+        if let Some(&anisotropy) = args.get_one::<u16>("anisotropy") {
+            settings.graphics.anisotropy = anisotropy;
+        }
+        settings.graphics.texture_atlas = args.get_flag("texture-atlas");
+        if let Some(&scale) = args.get_one::<u32>("screenshot-scale") {
+            settings.graphics.screenshot_scale = scale;
+        }
+        if let Some(&samples) = args.get_one::<u32>("msaa") {
+            settings.graphics.msaa = samples;
+        }
+        if let Some(mode) = args.get_one::<String>("present-mode") {
+            settings.graphics.present_mode = match mode.as_str() {
+                "mailbox" => PresentMode::Mailbox,
+                "immediate" => PresentMode::Immediate,
+                _ => PresentMode::Vsync,
+            };
+        }
+        let post_process = &mut settings.graphics.post_process;
+        post_process.tonemapping = !args.get_flag("no-tonemapping");
+        post_process.fxaa = !args.get_flag("no-fxaa");
+        if let Some(&brightness) = args.get_one::<f32>("brightness") {
+            post_process.brightness = brightness;
+        }
+        if let Some(&gamma) = args.get_one::<f32>("gamma") {
+            post_process.gamma = gamma;
+        }
+        settings.shader_dir = args.get_one::<String>("watch-shaders").map(PathBuf::from);
+        if let Some(dir) = args.get_one::<String>("block-textures") {
+            settings.block_texture_dir = Some(PathBuf::from(dir));
+        }
+        if let Some(dir) = args.get_one::<String>("world") {
+            settings.world_dir = PathBuf::from(dir);
+        }
+        if let Some(&time_scale) = args.get_one::<f32>("time-scale") {
+            settings.time_scale = time_scale;
+        }
+        settings
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct GraphicsSettings {
+    /// Anisotropic filtering samples for block textures, from 1 (off) to 16.
+    pub anisotropy: u16,
+    /// Pack block textures into a 2D atlas instead of the layers of a texture array.
+    pub texture_atlas: bool,
+    /// Multiple of the window size high resolution screenshots are rendered at.
+    pub screenshot_scale: u32,
+    /// Multisample antialiasing samples per pixel: 1 (off), 2, 4 or 8. The renderer
+    /// falls back to the closest count the adapter supports.
+    pub msaa: u32,
+    pub present_mode: PresentMode,
+    pub post_process: PostProcessSettings,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            anisotropy: 1,
+            texture_atlas: false,
+            screenshot_scale: 4,
+            msaa: 1,
+            present_mode: PresentMode::Vsync,
+            post_process: PostProcessSettings::default(),
+        }
+    }
+}
+
+
+/// How frames are presented to the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentMode {
+    /// Wait for the display's refresh, no tearing.
+    Vsync,
+    /// Render as fast as possible and show the latest frame at every refresh, no
+    /// tearing. Falls back to vsync where unsupported.
+    Mailbox,
+    /// Show frames as soon as they are rendered, with tearing. Falls back to mailbox,
+    /// then vsync.
+    Immediate,
+}
+
+impl PresentMode {
+    pub const ALL: [PresentMode; 3] = [PresentMode::Vsync, PresentMode::Mailbox, PresentMode::Immediate];
+}
+
+
+/// Stages turning the HDR frame into the displayed one, see `PostProcess`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostProcessSettings {
+    /// Compress bright colours into the display range with a filmic curve instead of
+    /// clipping them.
+    pub tonemapping: bool,
+    /// Smooth jagged edges with fast approximate antialiasing.
+    pub fxaa: bool,
+    /// Exposure the frame is scaled by before tonemapping, 1 leaves it unchanged.
+    pub brightness: f32,
+    /// Gamma correction on top of the display's, above 1 brightens dark colours.
+    pub gamma: f32,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self { tonemapping: true, fxaa: true, brightness: 1.0, gamma: 1.0 }
+    }
+}